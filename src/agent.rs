@@ -0,0 +1,640 @@
+//! 人間、CPU、MCTS、外部エンジンなどの対局者と、それらを対局させる関数
+//!
+//! 外部エンジンとは1行ずつのテキストでやり取りする。
+//! - 対局開始時に`new black`または`new white`を送る
+//! - 手番が来たら`position <Board::to_position()の文字列>`を送り、`f5`のような手か`pass`を1行で受け取る
+//! - 終局時に`end <black|white|draw>`を、中断した場合は`end aborted`を送る
+
+use crate::board::{
+    coordinate_from_notation, coordinate_to_notation, Board, Choice, JudgeResult, Player,
+};
+use crate::cpu::{Noise, CPU};
+use crate::difficulty::Difficulty;
+use crate::error::{Error, Result};
+use crate::mcts::Mcts;
+use crate::reference::{Reference, ReferencePlayer};
+use rand::prelude::{SliceRandom, StdRng};
+use rand::{Rng, SeedableRng};
+use std::io::{stdin, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::time::Duration;
+
+/// 対局者
+pub trait Agent {
+    fn name(&self) -> String;
+
+    /// 次の一手を選ぶ
+    fn choose(&mut self, board: &Board) -> Result<Choice>;
+
+    /// 対局の途中経過を見て指示を出す(既定では`choose`で選んだ手を打つ)
+    fn act(&mut self, game: &Game) -> Result<Action> {
+        Ok(Action::Play(self.choose(game.board())?))
+    }
+
+    /// 人間が操作するか(待ったで戻る手番の判定に使う)
+    fn is_interactive(&self) -> bool {
+        false
+    }
+
+    /// 対局の開始時に、開始局面と自分の色を受け取る
+    fn game_start(&mut self, _board: &Board, _me: Player) {}
+
+    /// 終局時に棋譜を受け取る
+    fn game_end(&mut self, _record: &GameRecord) {}
+}
+
+/// 対局者が手番で出す指示
+#[derive(Clone, Debug)]
+pub enum Action {
+    Play(Choice),
+    /// 直前の自分の手番まで戻す
+    Undo,
+    /// 戻した手を次の自分の手番までやり直す
+    Redo,
+    /// 読み込んだ対局に置き換える
+    Load(Game),
+    /// 対局を中断する
+    Quit,
+}
+
+/// 待ったとやり直しのできる対局の途中経過
+#[derive(Clone, Debug)]
+pub struct Game {
+    /// 開始局面
+    pub start: Board,
+    /// パスを含めて打った順の手
+    pub choices: Vec<Choice>,
+    /// 各手を打つ前の盤面と今の盤面
+    boards: Vec<Board>,
+    /// 待ったで戻した手(最後に戻した手が末尾)
+    undone: Vec<Choice>,
+}
+
+impl Game {
+    pub fn new(start: &Board) -> Self {
+        Self {
+            start: start.clone(),
+            choices: Vec::new(),
+            boards: vec![start.clone()],
+            undone: Vec::new(),
+        }
+    }
+
+    /// 今の盤面
+    pub fn board(&self) -> &Board {
+        self.boards.last().unwrap()
+    }
+
+    /// 手を打つ。やり直せる手は捨てる
+    pub fn play(&mut self, choice: Choice) -> Result<JudgeResult> {
+        let result = self.apply(choice)?;
+        self.undone.clear();
+        Ok(result)
+    }
+
+    fn apply(&mut self, choice: Choice) -> Result<JudgeResult> {
+        let mut board = self.board().clone();
+        let result = board.update(choice)?;
+        self.choices.push(choice);
+        self.boards.push(board);
+        Ok(result)
+    }
+
+    /// 1手戻す。戻せる手が無ければ`false`
+    pub fn undo(&mut self) -> bool {
+        match self.choices.pop() {
+            Some(choice) => {
+                self.boards.pop();
+                self.undone.push(choice);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 戻した手を1手やり直す。やり直せる手が無ければ`false`
+    pub fn redo(&mut self) -> bool {
+        match self.undone.pop() {
+            Some(choice) => {
+                self.apply(choice).unwrap();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// パスを除いた"f5d6c3..."のような棋譜
+    pub fn moves(&self) -> String {
+        moves_to_notation(&self.choices)
+    }
+
+    /// 1行目に開始局面の`Board::to_position`、2行目に棋譜を書いた文字列
+    pub fn to_text(&self) -> String {
+        format!("{}\n{}\n", self.start.to_position(), self.moves())
+    }
+
+    /// `to_text`の形式から対局を復元する。パスは補って進める
+    pub fn from_text(text: &str) -> Result<Self> {
+        let mut lines = text.lines();
+        let start = lines
+            .next()
+            .ok_or_else(|| Error::Parse("Empty game!".to_string()))?;
+        let start = Board::from_position(start)?;
+        let moves = lines
+            .next()
+            .unwrap_or("")
+            .trim()
+            .chars()
+            .collect::<Vec<_>>();
+
+        let mut game = Self::new(&start);
+        for notation in moves.chunks(2) {
+            if game.board().is_game_finished() {
+                return Err(Error::GameOver);
+            }
+            if game.board().is_skip() {
+                game.play(Choice::Skip)?;
+            }
+
+            let notation = notation.iter().collect::<String>();
+            let co = coordinate_from_notation(&notation)
+                .ok_or_else(|| Error::Parse(format!("Invalid notation: {}", notation)))?;
+            game.play(Choice::Coordinate(co))?;
+        }
+
+        if game.board().is_game_finished() {
+            return Err(Error::Parse("The saved game is already over!".to_string()));
+        }
+        Ok(game)
+    }
+}
+
+fn moves_to_notation(choices: &[Choice]) -> String {
+    choices
+        .iter()
+        .filter_map(|choice| match choice {
+            Choice::Coordinate(co) => Some(coordinate_to_notation(*co)),
+            Choice::Skip => None,
+        })
+        .collect()
+}
+
+/// 1局分の記録
+#[derive(Clone, Debug)]
+pub struct GameRecord {
+    pub black: String,
+    pub white: String,
+    /// 開始局面
+    pub start: Board,
+    /// パスを含めて打った順の手
+    pub choices: Vec<Choice>,
+    /// 中断した場合は`JudgeResult::Continue`
+    pub result: JudgeResult,
+    /// 終局時の盤面
+    pub board: Board,
+}
+
+impl GameRecord {
+    /// パスを除いた"f5d6c3..."のような棋譜
+    pub fn moves(&self) -> String {
+        moves_to_notation(&self.choices)
+    }
+}
+
+/// 2人の対局者で盤面から終局まで対局する
+/// 打てない手を選んだ場合はエラーを表示し、人間が操作する対局者なら選び直させ、それ以外は反則負けとする
+/// 対局者がエラーを返した場合も反則負けとする
+/// 待ったとやり直しは、人間が操作する対局者の手番まで進めたり戻したりする
+pub fn play_agents(black: &mut dyn Agent, white: &mut dyn Agent, board: &Board) -> GameRecord {
+    black.game_start(board, Player::Black);
+    white.game_start(board, Player::White);
+
+    let mut game = Game::new(board);
+    let is_interactive =
+        |game: &Game, black: &dyn Agent, white: &dyn Agent| match game.board().player {
+            Player::Black => black.is_interactive(),
+            Player::White => white.is_interactive(),
+        };
+
+    let result = loop {
+        let action = if game.board().player == Player::Black {
+            black.act(&game)
+        } else {
+            white.act(&game)
+        };
+
+        let action = match action {
+            Ok(action) => action,
+            Err(e) => {
+                eprintln!("{}", e);
+                break JudgeResult::Win(game.board().player.next());
+            }
+        };
+
+        match action {
+            Action::Play(choice) => match game.play(choice) {
+                Err(e) if is_interactive(&game, black, white) => eprintln!("{}", e),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    break JudgeResult::Win(game.board().player.next());
+                }
+                Ok(JudgeResult::Continue) => (),
+                Ok(result) => break result,
+            },
+            Action::Undo => {
+                let mut undone = 0;
+                while game.undo() {
+                    undone += 1;
+                    if is_interactive(&game, black, white) {
+                        break;
+                    }
+                }
+
+                // 人間が操作する対局者の手番まで戻せなければ元に戻す
+                if undone == 0 || !is_interactive(&game, black, white) {
+                    for _ in 0..undone {
+                        game.redo();
+                    }
+                    eprintln!("Nothing to undo");
+                }
+            }
+            Action::Redo => {
+                if !game.redo() {
+                    eprintln!("Nothing to redo");
+                }
+                while !is_interactive(&game, black, white) && game.redo() {}
+            }
+            Action::Load(loaded) => game = loaded,
+            Action::Quit => break JudgeResult::Continue,
+        }
+    };
+
+    let record = GameRecord {
+        black: black.name(),
+        white: white.name(),
+        start: game.start.clone(),
+        board: game.board().clone(),
+        choices: game.choices,
+        result,
+    };
+    black.game_end(&record);
+    white.game_end(&record);
+    record
+}
+
+/// 指定した深さまで探索し、`noise`に従って乱雑さを加えて手を選ぶCPU
+pub struct CpuAgent {
+    pub cpu: CPU,
+    pub depth: usize,
+    pub noise: Noise,
+    /// 1手あたりの思考時間(指定すると時間内に終わる深さまでしか読まない)
+    pub time_limit: Option<Duration>,
+    /// 最善手以外の手をわざと選ぶ確率
+    pub blunder_prob: f64,
+    rng: StdRng,
+}
+
+impl CpuAgent {
+    /// `choose_best`で手を選ぶCPU
+    pub fn new(cpu: CPU, depth: usize) -> Self {
+        Self::with_noise(cpu, depth, Noise::default(), 0)
+    }
+
+    pub fn with_noise(cpu: CPU, depth: usize, noise: Noise, seed: u64) -> Self {
+        Self {
+            cpu,
+            depth,
+            noise,
+            time_limit: None,
+            blunder_prob: 0.0,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// 難易度の設定に従って手を選ぶCPU
+    pub fn with_difficulty(cpu: CPU, difficulty: &Difficulty, seed: u64) -> Self {
+        let mut agent = Self::with_noise(cpu, difficulty.depth, difficulty.noise, seed);
+        agent.time_limit = difficulty.time_limit_ms.map(Duration::from_millis);
+        agent.blunder_prob = difficulty.blunder_prob;
+        agent
+    }
+}
+
+impl Agent for CpuAgent {
+    fn name(&self) -> String {
+        format!("cpu-d{}", self.depth)
+    }
+
+    fn choose(&mut self, board: &Board) -> Result<Choice> {
+        if board.is_skip() {
+            return Ok(Choice::Skip);
+        }
+
+        if let Some(choice) = CPU::choose_random(board, &self.noise, &mut self.rng) {
+            return Ok(choice);
+        }
+
+        // 温度がある場合は各手の評価値を1度だけ求め、最善手と抽選の両方に使う
+        let choices = if self.noise.temperature > 0.0 {
+            Some(match self.time_limit {
+                Some(time_limit) => {
+                    self.cpu
+                        .eval_choices_within(board, self.depth, time_limit)
+                        .0
+                }
+                None => self.cpu.eval_choices(board, self.depth),
+            })
+        } else {
+            None
+        };
+        let best = match (&choices, self.time_limit) {
+            (Some(choices), _) => CPU::best_choice(choices),
+            (None, Some(time_limit)) => {
+                self.cpu.choose_best_within(board, self.depth, time_limit).0
+            }
+            (None, None) => self.cpu.choose_best(board, self.depth),
+        };
+
+        if self.blunder_prob > 0.0 && self.rng.gen::<f64>() < self.blunder_prob {
+            let others = board
+                .legal_choices()
+                .into_iter()
+                .filter(|&choice| choice != best)
+                .collect::<Vec<_>>();
+            if let Some(&choice) = others.choose(&mut self.rng) {
+                return Ok(choice);
+            }
+        }
+
+        Ok(match choices {
+            Some(choices) => CPU::sample_choice(&choices, self.noise.temperature, &mut self.rng),
+            None => best,
+        })
+    }
+}
+
+impl Agent for Mcts {
+    fn name(&self) -> String {
+        "mcts".to_string()
+    }
+
+    fn choose(&mut self, board: &Board) -> Result<Choice> {
+        Ok(Mcts::choose(self, board))
+    }
+
+    fn game_start(&mut self, _board: &Board, _me: Player) {
+        self.reset();
+    }
+}
+
+/// ランダムな手などを選ぶ基準の対戦相手
+pub struct ReferenceAgent {
+    pub player: ReferencePlayer,
+}
+
+impl ReferenceAgent {
+    pub fn new(reference: Reference, seed: u64) -> Self {
+        Self {
+            player: ReferencePlayer::new(reference, seed),
+        }
+    }
+
+    /// 合法手からランダムに選ぶ対局者
+    pub fn random(seed: u64) -> Self {
+        Self::new(Reference::Random, seed)
+    }
+}
+
+impl Agent for ReferenceAgent {
+    fn name(&self) -> String {
+        self.player.reference.name()
+    }
+
+    fn choose(&mut self, board: &Board) -> Result<Choice> {
+        Ok(self.player.choose(board))
+    }
+
+    fn game_start(&mut self, _board: &Board, _me: Player) {
+        self.player.reset();
+    }
+}
+
+/// 標準入力からコマンドを受け取る人間の対局者
+/// 手番の度に盤面を表示し、`HUMAN_COMMANDS`のコマンドを1行ずつ受け付ける
+pub struct HumanAgent {
+    /// `hint`と`eval`に使うCPU
+    pub evaluator: CPU,
+    /// `hint`と`eval`で読む深さ
+    pub depth: usize,
+}
+
+/// `HumanAgent`のコマンドの説明
+pub const HUMAN_COMMANDS: &str = "\
+f5          put a disc (column a-h, row 1-8)
+pass        pass when you have no legal moves
+undo        take back to your previous turn
+redo        replay moves taken back by undo
+hint        show the best move
+eval        show the evaluation of each legal move
+save <file> save the game
+load <file> load a saved game
+board       show the board
+quit        quit the game
+help        show this help";
+
+impl HumanAgent {
+    fn eval(&self, board: &Board) {
+        eprintln!("static score: {}", self.evaluator.eval_board(board));
+
+        let mut scores = self.evaluator.eval_choices(board, self.depth);
+        scores.sort_by_key(|&(_, score)| -score);
+        for (choice, score) in scores {
+            if let Choice::Coordinate(co) = choice {
+                eprintln!("{}: {}", coordinate_to_notation(co), score);
+            }
+        }
+    }
+}
+
+impl Agent for HumanAgent {
+    fn name(&self) -> String {
+        "human".to_string()
+    }
+
+    /// 待ったなどのできない場面では手とパス以外のコマンドを受け付けない
+    fn choose(&mut self, board: &Board) -> Result<Choice> {
+        loop {
+            match self.act(&Game::new(board))? {
+                Action::Play(choice) => return Ok(choice),
+                _ => eprintln!("Only moves are available here"),
+            }
+        }
+    }
+
+    /// 入力が閉じられたら対局を中断する
+    fn act(&mut self, game: &Game) -> Result<Action> {
+        let board = game.board();
+        eprintln!("{:?}", board);
+        if board.is_skip() {
+            eprintln!("No legal moves. Type pass.");
+        }
+
+        loop {
+            let mut buf = String::new();
+            if stdin().read_line(&mut buf)? == 0 {
+                return Ok(Action::Quit);
+            }
+
+            let mut words = buf.split_whitespace();
+            let command = match words.next() {
+                Some(command) => command.to_ascii_lowercase(),
+                None => continue,
+            };
+            let file_name = words.next();
+
+            match (command.as_str(), file_name) {
+                ("quit", _) => return Ok(Action::Quit),
+                ("undo", _) => return Ok(Action::Undo),
+                ("redo", _) => return Ok(Action::Redo),
+                ("board", _) => eprintln!("{:?}", board),
+                ("help", _) => eprintln!("{}", HUMAN_COMMANDS),
+                ("pass", _) if board.is_skip() => return Ok(Action::Play(Choice::Skip)),
+                ("pass", _) => eprintln!("You have legal moves"),
+                ("hint", _) => match self.evaluator.choose_best(board, self.depth) {
+                    Choice::Coordinate(co) => eprintln!("hint: {}", coordinate_to_notation(co)),
+                    Choice::Skip => eprintln!("hint: pass"),
+                },
+                ("eval", _) => self.eval(board),
+                ("save", Some(_)) if board.is_game_finished() => {
+                    eprintln!("Game is already over")
+                }
+                ("save", Some(file_name)) => match std::fs::write(file_name, game.to_text()) {
+                    Ok(()) => eprintln!("Saved to {}", file_name),
+                    Err(e) => eprintln!("{}: {}", file_name, e),
+                },
+                ("load", Some(file_name)) => {
+                    let loaded = std::fs::read_to_string(file_name)
+                        .map_err(|e| e.to_string())
+                        .and_then(|text| Game::from_text(&text).map_err(|e| e.to_string()));
+                    match loaded {
+                        Ok(loaded) => return Ok(Action::Load(loaded)),
+                        Err(e) => eprintln!("{}: {}", file_name, e),
+                    }
+                }
+                ("save", None) | ("load", None) => eprintln!("Usage: {} <file>", command),
+                (notation, _) => match coordinate_from_notation(notation) {
+                    Some(co) if board.is_possible(co) => {
+                        return Ok(Action::Play(Choice::Coordinate(co)))
+                    }
+                    Some(_) => eprintln!("Illegal move: {}", notation),
+                    None => eprintln!("Unknown command: {} (type help)", notation),
+                },
+            }
+        }
+    }
+
+    fn is_interactive(&self) -> bool {
+        true
+    }
+}
+
+/// 外部エンジンを子プロセスとして起動し、モジュールの説明の形式でやり取りする
+pub struct ExternalAgent {
+    name: String,
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+fn color_name(player: Player) -> &'static str {
+    match player {
+        Player::Black => "black",
+        Player::White => "white",
+    }
+}
+
+impl ExternalAgent {
+    /// 空白で区切ったコマンドを起動する
+    pub fn spawn(command: &str) -> Result<Self> {
+        let mut args = command.split_whitespace();
+        let program = args
+            .next()
+            .ok_or_else(|| Error::Engine("Empty engine command!".to_string()))?;
+
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+
+        Ok(Self {
+            name: command.to_string(),
+            child,
+            stdin,
+            stdout,
+        })
+    }
+
+    fn send(&mut self, line: &str) -> Result<()> {
+        writeln!(self.stdin, "{}", line)?;
+        Ok(self.stdin.flush()?)
+    }
+
+    fn receive(&mut self) -> Result<String> {
+        let mut buf = String::new();
+        if self.stdout.read_line(&mut buf)? == 0 {
+            return Err(Error::Engine("Engine closed its output!".to_string()));
+        }
+        Ok(buf.trim().to_string())
+    }
+}
+
+impl Agent for ExternalAgent {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// エンジンが応答しない場合や読めない手を返した場合はエラーを返す
+    fn choose(&mut self, board: &Board) -> Result<Choice> {
+        self.send(&format!("position {}", board.to_position()))?;
+
+        let reply = self.receive()?;
+        if reply == "pass" {
+            return Ok(Choice::Skip);
+        }
+
+        match coordinate_from_notation(&reply) {
+            Some(co) => Ok(Choice::Coordinate(co)),
+            None => Err(Error::Engine(format!(
+                "Invalid move from engine: {}",
+                reply
+            ))),
+        }
+    }
+
+    fn game_start(&mut self, _board: &Board, me: Player) {
+        if let Err(e) = self.send(&format!("new {}", color_name(me))) {
+            eprintln!("{}: {}", self.name, e);
+        }
+    }
+
+    fn game_end(&mut self, record: &GameRecord) {
+        let result = match record.result {
+            JudgeResult::Win(winner) => color_name(winner),
+            JudgeResult::Draw => "draw",
+            JudgeResult::Continue => "aborted",
+        };
+        if let Err(e) = self.send(&format!("end {}", result)) {
+            eprintln!("{}: {}", self.name, e);
+        }
+    }
+}
+
+impl Drop for ExternalAgent {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,131 @@
+use crate::binary::{decode_cpus, encode_cpus, is_binary};
+use crate::checkpoint::write_atomic;
+use crate::cpu::CPU;
+use crate::error::{Error, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 保存された優勝CPUとその記録
+/// 古いファイルには世代数と時刻が無い
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub struct ArchiveEntry {
+    #[serde(default)]
+    pub generation: Option<usize>,
+    /// 保存した時刻(UNIX時間の秒)
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    #[serde(flatten)]
+    pub cpu: CPU,
+}
+
+impl ArchiveEntry {
+    /// 現在時刻で記録を作る
+    pub fn new(cpu: CPU, generation: usize) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .ok();
+
+        Self {
+            generation: Some(generation),
+            timestamp,
+            cpu,
+        }
+    }
+
+    /// `CPU::log`と同じく改行を加えて1行のJSONで追記する
+    pub fn log(&self, log_file: &mut File) -> Result<()> {
+        let mut value = serde_json::to_value(self)?;
+        value["version"] = serde_json::Value::from(CPU::VERSION);
+        log_file.write_all(format!("\n{}", value).as_bytes())?;
+        Ok(log_file.flush()?)
+    }
+}
+
+/// 優勝CPUを追記したファイル全体
+#[derive(Clone, Debug, Default)]
+pub struct Archive {
+    /// 古い順の記録
+    pub entries: Vec<ArchiveEntry>,
+    /// 読み込んだファイルがバイナリ形式か
+    pub binary: bool,
+}
+
+impl Archive {
+    /// JSON形式なら全ての行を、バイナリ形式なら全てのCPUを読み込む
+    pub fn from_log_file(log_file: &mut File) -> Result<Self> {
+        let mut bytes = Vec::new();
+        log_file.read_to_end(&mut bytes)?;
+
+        if is_binary(&bytes) {
+            let entries = decode_cpus(&bytes)?
+                .into_iter()
+                .map(|cpu| ArchiveEntry {
+                    generation: None,
+                    timestamp: None,
+                    cpu,
+                })
+                .collect();
+            return Ok(Self {
+                entries,
+                binary: true,
+            });
+        }
+
+        let mut entries = Vec::new();
+        for (line_num, line) in String::from_utf8_lossy(&bytes).lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let entry = serde_json::from_str(line)
+                .map_err(Error::from)
+                .and_then(CPU::migrate_json)
+                .and_then(|value| Ok(serde_json::from_value(value)?))
+                .map_err(|e| Error::Parse(format!("line {}: {}", line_num + 1, e)))?;
+            entries.push(entry);
+        }
+
+        Ok(Self {
+            entries,
+            binary: false,
+        })
+    }
+
+    /// 記録を最新として読み込んだファイルと同じ形式で書き足す
+    /// バイナリ形式は追記できないので全てのCPUを書き直す
+    pub fn push(&mut self, entry: ArchiveEntry, path: &Path) -> Result<()> {
+        if self.binary {
+            self.entries.push(entry);
+            let cpus: Vec<CPU> = self.entries.iter().map(|entry| entry.cpu.clone()).collect();
+            return Ok(write_atomic(path, &encode_cpus(&cpus))?);
+        }
+
+        let mut log_file = OpenOptions::new().append(true).create(true).open(path)?;
+        entry.log(&mut log_file)?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    pub fn get(&self, index: usize) -> Option<&ArchiveEntry> {
+        self.entries.get(index)
+    }
+
+    /// 指定した世代の記録のうち最も新しいもの
+    pub fn find_generation(&self, generation: usize) -> Option<&ArchiveEntry> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|entry| entry.generation == Some(generation))
+    }
+
+    /// "3"なら0から数えて3番目(`list`の番号)、"g25"なら25世代目の記録を探す
+    pub fn select(&self, selector: &str) -> Option<&ArchiveEntry> {
+        match selector.strip_prefix('g') {
+            Some(generation) => self.find_generation(generation.parse().ok()?),
+            None => self.get(selector.parse().ok()?),
+        }
+    }
+}
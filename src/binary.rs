@@ -0,0 +1,353 @@
+//! CPUとトーナメントのバイナリ形式
+//!
+//! 先頭に`MAGIC`、リトルエンディアンの`u16`のバージョン、種類を表す1バイトを置き、
+//! その後に中身が続く。CPUは序盤から順に各段階の重みを`i8`で並べた44バイトで表す。
+//! バージョン2からトーナメントの後ろにCMA-ESの分布が続く。
+
+use crate::cma::{CmaState, DIM};
+use crate::cpu::{CPU, WEIGHT_LEN};
+use crate::error::{Error, Result};
+use crate::tournament::Tournament;
+
+pub const MAGIC: [u8; 4] = *b"BOTH";
+pub const VERSION: u16 = 2;
+
+const KIND_CPUS: u8 = 0;
+const KIND_TOURNAMENT: u8 = 1;
+
+const CPU_BYTES: usize = WEIGHT_LEN * 4;
+
+/// バイナリ形式のデータか
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+/// ヘッダの種類を表す1バイト
+fn kind(bytes: &[u8]) -> Option<u8> {
+    bytes.get(MAGIC.len() + 2).copied()
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::Parse(message.to_string())
+}
+
+fn write_header(buf: &mut Vec<u8>, kind: u8) {
+    buf.extend_from_slice(&MAGIC);
+    buf.extend_from_slice(&VERSION.to_le_bytes());
+    buf.push(kind);
+}
+
+fn write_cpus(buf: &mut Vec<u8>, cpus: &[CPU]) {
+    buf.extend_from_slice(&(cpus.len() as u32).to_le_bytes());
+    for cpu in cpus {
+        for weights in cpu.stages().iter() {
+            buf.extend(weights.iter().map(|&weight| weight as u8));
+        }
+    }
+}
+
+fn write_f64s(buf: &mut Vec<u8>, values: &[f64]) {
+    for value in values {
+        buf.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// 分布の有無を1バイトで表し、有ればその後に次元数と中身を続ける
+fn write_cma(buf: &mut Vec<u8>, cma: &Option<CmaState>) {
+    let state = match cma {
+        Some(state) => state,
+        None => {
+            buf.push(0);
+            return;
+        }
+    };
+
+    buf.push(1);
+    buf.extend_from_slice(&(state.mean.len() as u32).to_le_bytes());
+    write_f64s(buf, &state.mean);
+    write_f64s(buf, &[state.sigma]);
+    for row in &state.covariance {
+        write_f64s(buf, row);
+    }
+    write_f64s(buf, &state.path_sigma);
+    write_f64s(buf, &state.path_c);
+    buf.extend_from_slice(&(state.updates as u64).to_le_bytes());
+}
+
+/// バイト列を先頭から読む
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    version: u16,
+}
+
+impl<'a> Reader<'a> {
+    /// ヘッダを確認し、その後ろを読むReaderを作る
+    fn new(bytes: &'a [u8], kind: u8) -> Result<Self> {
+        if !is_binary(bytes) {
+            return Err(invalid_data("Not a bit_othello binary file!"));
+        }
+
+        let mut reader = Self {
+            bytes,
+            pos: MAGIC.len(),
+            version: 0,
+        };
+
+        reader.version = u16::from_le_bytes([reader.u8()?, reader.u8()?]);
+        if reader.version > VERSION {
+            return Err(invalid_data("Unsupported binary version!"));
+        }
+
+        if reader.u8()? != kind {
+            return Err(invalid_data("Unexpected binary kind!"));
+        }
+
+        Ok(reader)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < self.pos + len {
+            return Err(invalid_data("Binary file is truncated!"));
+        }
+
+        let slice = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn f64s(&mut self, len: usize) -> Result<Vec<f64>> {
+        (0..len).map(|_| Ok(f64::from_bits(self.u64()?))).collect()
+    }
+
+    /// バージョン1には分布が無い
+    fn cma(&mut self) -> Result<Option<CmaState>> {
+        if self.version < 2 || self.u8()? == 0 {
+            return Ok(None);
+        }
+
+        let dim = self.u32()? as usize;
+        if dim != DIM {
+            return Err(invalid_data("Unexpected CMA-ES dimension!"));
+        }
+        let mean = self.f64s(dim)?;
+        let sigma = self.f64s(1)?[0];
+        let covariance = (0..dim)
+            .map(|_| self.f64s(dim))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Some(CmaState {
+            mean,
+            sigma,
+            covariance,
+            path_sigma: self.f64s(dim)?,
+            path_c: self.f64s(dim)?,
+            updates: self.u64()? as usize,
+        }))
+    }
+
+    fn cpus(&mut self) -> Result<Vec<CPU>> {
+        let len = self.u32()? as usize;
+        let bytes = self.take(len * CPU_BYTES)?;
+
+        Ok(bytes
+            .chunks(CPU_BYTES)
+            .map(|chunk| {
+                let mut stages = [[0; WEIGHT_LEN]; 4];
+                for (stage, weights) in stages.iter_mut().enumerate() {
+                    for (k, weight) in weights.iter_mut().enumerate() {
+                        *weight = chunk[stage * WEIGHT_LEN + k] as i8;
+                    }
+                }
+
+                CPU::from_stages(stages)
+            })
+            .collect())
+    }
+
+    fn finish(&self) -> Result<()> {
+        if self.pos != self.bytes.len() {
+            return Err(invalid_data("Trailing bytes in binary file!"));
+        }
+
+        Ok(())
+    }
+}
+
+/// CPUの列をバイナリにする
+pub fn encode_cpus(cpus: &[CPU]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(11 + cpus.len() * CPU_BYTES);
+    write_header(&mut buf, KIND_CPUS);
+    write_cpus(&mut buf, cpus);
+    buf
+}
+
+pub fn decode_cpus(bytes: &[u8]) -> Result<Vec<CPU>> {
+    let mut reader = Reader::new(bytes, KIND_CPUS)?;
+    let cpus = reader.cpus()?;
+    reader.finish()?;
+    Ok(cpus)
+}
+
+/// トーナメントをバイナリにする
+pub fn encode_tournament(tournament: &Tournament) -> Vec<u8> {
+    let cpu_num = tournament.cpus.len() + tournament.hall_of_fame.len();
+    let mut buf = Vec::with_capacity(35 + cpu_num * CPU_BYTES);
+    write_header(&mut buf, KIND_TOURNAMENT);
+    buf.extend_from_slice(&(tournament.generation as u64).to_le_bytes());
+    buf.extend_from_slice(&tournament.seed.to_le_bytes());
+    buf.extend_from_slice(&(tournament.island_num as u32).to_le_bytes());
+    write_cpus(&mut buf, &tournament.cpus);
+    write_cpus(&mut buf, &tournament.hall_of_fame);
+    write_cma(&mut buf, &tournament.cma);
+    buf
+}
+
+pub fn decode_tournament(bytes: &[u8]) -> Result<Tournament> {
+    let mut reader = Reader::new(bytes, KIND_TOURNAMENT)?;
+    let tournament = Tournament {
+        version: Tournament::VERSION,
+        generation: reader.u64()? as usize,
+        seed: reader.u64()?,
+        island_num: reader.u32()? as usize,
+        cpus: reader.cpus()?,
+        hall_of_fame: reader.cpus()?,
+        cma: reader.cma()?,
+    };
+    reader.finish()?;
+    Ok(tournament)
+}
+
+/// バイナリ形式とJSON形式を相互に変換する
+/// JSON形式のCPUは`CPU::log`と同じく1行に1体とする
+pub fn convert(bytes: &[u8]) -> Result<Vec<u8>> {
+    if is_binary(bytes) {
+        if kind(bytes) == Some(KIND_TOURNAMENT) {
+            return Ok(serde_json::to_vec(&decode_tournament(bytes)?)?);
+        }
+
+        let mut buf = String::new();
+        for cpu in decode_cpus(bytes)? {
+            buf.push('\n');
+            buf.push_str(&cpu.to_json()?);
+        }
+        return Ok(buf.into_bytes());
+    }
+
+    if let Ok(tournament) = Tournament::from_json(bytes) {
+        return Ok(encode_tournament(&tournament));
+    }
+
+    let str = std::str::from_utf8(bytes).map_err(|_| invalid_data("Not a UTF-8 file!"))?;
+    let cpus = str
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(CPU::from_json)
+        .collect::<Result<Vec<_>>>()?;
+    Ok(encode_cpus(&cpus))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cma::from_cpu;
+    use rand::prelude::StdRng;
+    use rand::SeedableRng;
+
+    fn assert_same_tournament(left: &Tournament, right: &Tournament) {
+        assert_eq!(left.generation, right.generation);
+        assert_eq!(left.seed, right.seed);
+        assert_eq!(left.island_num, right.island_num);
+        assert_eq!(left.cpus, right.cpus);
+        assert_eq!(left.hall_of_fame, right.hall_of_fame);
+        assert_eq!(left.cma, right.cma);
+    }
+
+    fn tournament_with_cma() -> Tournament {
+        let mut tournament = Tournament::new_random(4, 7);
+        tournament.generation = 12;
+        tournament.island_num = 2;
+        tournament.hall_of_fame = tournament.cpus[..2].to_vec();
+
+        let mut cma = CmaState::new(from_cpu(&tournament.cpus[0]), 3.5);
+        cma.path_sigma[0] = 0.25;
+        cma.path_c[1] = -0.5;
+        cma.updates = 3;
+        tournament.cma = Some(cma);
+        tournament
+    }
+
+    #[test]
+    fn cpus_round_trip() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let cpus: Vec<_> = (0..3).map(|_| CPU::new_random(&mut rng)).collect();
+
+        let bytes = encode_cpus(&cpus);
+        assert!(is_binary(&bytes));
+        assert_eq!(decode_cpus(&bytes).unwrap(), cpus);
+        assert!(decode_cpus(&encode_cpus(&[])).unwrap().is_empty());
+    }
+
+    #[test]
+    fn tournament_round_trips() {
+        let tournament = Tournament::new_random(4, 7);
+        let decoded = decode_tournament(&encode_tournament(&tournament)).unwrap();
+        assert_same_tournament(&decoded, &tournament);
+
+        let tournament = tournament_with_cma();
+        let decoded = decode_tournament(&encode_tournament(&tournament)).unwrap();
+        assert_same_tournament(&decoded, &tournament);
+    }
+
+    #[test]
+    fn convert_round_trips_tournament() {
+        let tournament = tournament_with_cma();
+        let bytes = encode_tournament(&tournament);
+
+        let json = convert(&bytes).unwrap();
+        assert!(!is_binary(&json));
+        assert_eq!(convert(&json).unwrap(), bytes);
+    }
+
+    #[test]
+    fn truncated_binary_is_rejected() {
+        let bytes = encode_tournament(&tournament_with_cma());
+        assert!(matches!(
+            decode_tournament(&bytes[..bytes.len() - 1]),
+            Err(Error::Parse(_))
+        ));
+        assert!(matches!(decode_cpus(&bytes), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn unexpected_cma_dimension_is_rejected() {
+        let tournament = tournament_with_cma();
+        let mut bytes = encode_tournament(&tournament);
+
+        // 分布の有無の1バイトの直後が次元数
+        let cma_len = 1 + 4 + 8 * (3 * DIM + 1 + DIM * DIM) + 8;
+        let dim_at = bytes.len() - cma_len + 1;
+        assert_eq!(bytes[dim_at - 1], 1);
+        bytes[dim_at..dim_at + 4].copy_from_slice(&(DIM as u32 - 1).to_le_bytes());
+
+        match decode_tournament(&bytes) {
+            Err(Error::Parse(message)) => assert_eq!(message, "Unexpected CMA-ES dimension!"),
+            result => panic!("unexpected result: {:?}", result.map(|t| t.generation)),
+        }
+    }
+}
@@ -1,8 +1,9 @@
 use crate::board::Player::Black;
+use crate::error::{Error, Result};
 use std::fmt::{Debug, Formatter};
 use std::mem::swap;
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum Player {
     Black,
     White,
@@ -21,6 +22,25 @@ pub type BitBoard = usize;
 pub type Coordinate = (usize, usize);
 pub type ICoordinate = (isize, isize);
 
+/// "f5"のような棋譜表記を座標に変換する
+pub fn coordinate_from_notation(notation: &str) -> Option<Coordinate> {
+    let mut chars = notation.chars();
+    let column = chars.next()?.to_ascii_lowercase();
+    let row = chars.next()?;
+
+    if chars.next().is_some() || !('a'..='h').contains(&column) || !('1'..='8').contains(&row) {
+        return None;
+    }
+
+    Some((row as usize - '1' as usize, column as usize - 'a' as usize))
+}
+
+/// 座標を"f5"のような棋譜表記に変換する
+pub fn coordinate_to_notation(co: Coordinate) -> String {
+    let (i, j) = co;
+    format!("{}{}", (b'a' + j as u8) as char, i + 1)
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum JudgeResult {
     Continue,
@@ -34,7 +54,7 @@ pub enum Choice {
     Coordinate(Coordinate),
 }
 
-#[derive(Clone, Eq, PartialEq)]
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
 /// データの重さ的にはCopy
 pub struct Board {
     pub turn: usize,
@@ -55,10 +75,10 @@ impl Debug for Board {
 
         let dialog = format!("<Turn: {}>\nNext Player: {:?}", self.turn, self.player);
 
-        let mut str = " |0|1|2|3|4|5|6|7|\n".to_string();
+        let mut str = " |a|b|c|d|e|f|g|h|\n".to_string();
 
         for i in 0..8 {
-            let mut s = format!("{}|", i);
+            let mut s = format!("{}|", i + 1);
 
             for j in 0..8 {
                 let piece = if (black & 1 << (63 - i * 8 - j)) != 0 {
@@ -76,14 +96,89 @@ impl Debug for Board {
             }
 
             str.push_str(&s);
-            str.push_str("\n");
+            str.push('\n');
         }
 
         write!(f, "{}\n{}", dialog, str)
     }
 }
 
+impl Default for Board {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Board {
+    /// 黒を`X`、白を`O`、空きを`-`としてa1, b1, ..., h1, a2, ..., h8の順に並べた64文字と、
+    /// 空白の後に次の手番(`black`か`white`)を続けた文字列にする
+    pub fn to_position(&self) -> String {
+        let (black, white) = match self.player {
+            Player::Black => (self.player_board, self.opponent_board),
+            Player::White => (self.opponent_board, self.player_board),
+        };
+
+        let squares = (0..64)
+            .map(|k| {
+                if black & 1 << (63 - k) != 0 {
+                    'X'
+                } else if white & 1 << (63 - k) != 0 {
+                    'O'
+                } else {
+                    '-'
+                }
+            })
+            .collect::<String>();
+        let player = match self.player {
+            Player::Black => "black",
+            Player::White => "white",
+        };
+
+        format!("{} {}", squares, player)
+    }
+
+    /// `to_position`の形式の文字列から盤面を作る(手番を省略すると黒)
+    pub fn from_position(position: &str) -> Result<Self> {
+        let mut words = position.split_whitespace();
+        let squares = words
+            .next()
+            .ok_or_else(|| Error::Parse("Empty position!".to_string()))?;
+        let player = match words.next().map(|word| word.to_ascii_lowercase()) {
+            None => Player::Black,
+            Some(word) if word == "black" || word == "x" => Player::Black,
+            Some(word) if word == "white" || word == "o" => Player::White,
+            Some(_) => return Err(Error::Parse("Invalid player in position!".to_string())),
+        };
+        if words.next().is_some() || squares.chars().count() != 64 {
+            return Err(Error::Parse(
+                "Position must be 64 squares and a player!".to_string(),
+            ));
+        }
+
+        let (mut black, mut white) = (0, 0);
+        for (k, square) in squares.chars().enumerate() {
+            match square.to_ascii_uppercase() {
+                'X' => black |= 1 << (63 - k),
+                'O' => white |= 1 << (63 - k),
+                '-' | '.' => (),
+                _ => return Err(Error::Parse("Invalid square in position!".to_string())),
+            }
+        }
+
+        let (player_board, opponent_board): (BitBoard, BitBoard) = match player {
+            Player::Black => (black, white),
+            Player::White => (white, black),
+        };
+
+        Ok(Self {
+            // 初期盤面の4石で1手目とする
+            turn: ((black | white).count_ones() as usize).saturating_sub(3),
+            player,
+            player_board,
+            opponent_board,
+        })
+    }
+
     /// 初期盤面の作成
     pub fn new() -> Self {
         Self {
@@ -188,6 +283,16 @@ impl Board {
         1 << (63 - i * 8 - j)
     }
 
+    /// 設置可能な座標を列挙する
+    pub fn legal_choices(&self) -> Vec<Choice> {
+        let legal = self.make_legal_board();
+
+        (0..64)
+            .filter(|k| (legal & 1 << (63 - k)) != 0)
+            .map(|k| Choice::Coordinate((k / 8, k % 8)))
+            .collect()
+    }
+
     /// 設置可能か
     pub fn is_possible(&self, co: Coordinate) -> bool {
         (Board::coordinate_to_bit(co) & self.make_legal_board()) != 0
@@ -201,10 +306,19 @@ impl Board {
     }
 
     /// Choiceで番面を更新
-    pub fn update(&mut self, choice: Choice) -> Result<JudgeResult, &'static str> {
+    /// パスは打てる場所が無い場合にしかできない
+    /// 終局した盤面ではどちらも`Error::GameOver`になる
+    pub fn update(&mut self, choice: Choice) -> Result<JudgeResult> {
         if let Choice::Skip = choice {
+            if self.make_legal_board() != 0 {
+                return Err(Error::IllegalPass);
+            }
+            if self.is_game_finished() {
+                return Err(Error::GameOver);
+            }
+
             self.skip();
-            return Ok(JudgeResult::Continue);
+            return Ok(self.judge());
         }
 
         let co = match choice {
@@ -213,32 +327,39 @@ impl Board {
         };
 
         let (i, j) = co;
-        let put = Board::coordinate_to_bit(co);
-
         if !(i < 8 && j < 8) {
-            return Err("Out of index!");
+            return Err(Error::OutOfBounds(co));
         }
         
-        if !self.is_possible(co) {
-            return Err("Impposible Choice!");
+        let legal = self.make_legal_board();
+        if legal == 0 && self.is_game_finished() {
+            return Err(Error::GameOver);
+        }
+        if legal & Board::coordinate_to_bit(co) == 0 {
+            return Err(Error::IllegalMove(co));
         }
 
-        self.reverse(put);
+        self.reverse(Board::coordinate_to_bit(co));
 
         self.skip();
 
-        if self.is_game_finished() {
-            let (player, opponent) = self.calc_now_score();
-            return if player == opponent {
-                Ok(JudgeResult::Draw)
-            } else if player > opponent {
-                Ok(JudgeResult::Win(self.player))
-            } else {
-                Ok(JudgeResult::Win(self.player.next()))
-            };
+        Ok(self.judge())
+    }
+
+    /// 終局していれば勝敗を返す
+    fn judge(&self) -> JudgeResult {
+        if !self.is_game_finished() {
+            return JudgeResult::Continue;
         }
 
-        Ok(JudgeResult::Continue)
+        let (player, opponent) = self.calc_now_score();
+        if player == opponent {
+            JudgeResult::Draw
+        } else if player > opponent {
+            JudgeResult::Win(self.player)
+        } else {
+            JudgeResult::Win(self.player.next())
+        }
     }
 
     /// ひっくり返る場所を可変更新
@@ -293,7 +414,7 @@ impl Board {
     }
 
     /// 両方の手が存在しないか
-    fn is_game_finished(&self) -> bool {
+    pub fn is_game_finished(&self) -> bool {
         let player_legal_board = self.make_legal_board();
 
         let opponent_board = Self {
@@ -316,3 +437,116 @@ impl Board {
         (player_num, opponent_num)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 黒番で黒に打てる手が無く、白はc1に打てる盤面
+    fn black_must_pass() -> Board {
+        Board::from_position(&format!("OX{} black", "-".repeat(62))).unwrap()
+    }
+
+    #[test]
+    fn pass_with_legal_moves_is_illegal() {
+        let mut board = Board::new();
+
+        assert!(matches!(
+            board.update(Choice::Skip),
+            Err(Error::IllegalPass)
+        ));
+        assert_eq!(board, Board::new());
+    }
+
+    #[test]
+    fn pass_without_legal_moves_hands_over_the_turn() {
+        let mut board = black_must_pass();
+
+        assert!(board.is_skip());
+        assert!(matches!(
+            board.clone().update(Choice::Coordinate((0, 2))),
+            Err(Error::IllegalMove((0, 2)))
+        ));
+        assert_eq!(board.update(Choice::Skip).unwrap(), JudgeResult::Continue);
+        assert_eq!(board.player, Player::White);
+        assert_eq!(
+            board.update(Choice::Coordinate((0, 2))).unwrap(),
+            JudgeResult::Win(Player::White)
+        );
+    }
+
+    #[test]
+    fn moves_after_the_end_of_the_game_are_rejected() {
+        let position = format!("{}- black", "X".repeat(63));
+        let mut board = Board::from_position(&position).unwrap();
+
+        assert!(board.is_game_finished());
+        assert!(matches!(board.update(Choice::Skip), Err(Error::GameOver)));
+        assert!(matches!(
+            board.update(Choice::Coordinate((7, 7))),
+            Err(Error::GameOver)
+        ));
+    }
+
+    #[test]
+    fn out_of_bounds_moves_are_rejected() {
+        let mut board = Board::new();
+
+        assert!(matches!(
+            board.update(Choice::Coordinate((8, 0))),
+            Err(Error::OutOfBounds((8, 0)))
+        ));
+    }
+
+    #[test]
+    fn notation_round_trips_every_square() {
+        for i in 0..8 {
+            for j in 0..8 {
+                let notation = coordinate_to_notation((i, j));
+                assert_eq!(coordinate_from_notation(&notation), Some((i, j)));
+            }
+        }
+
+        assert_eq!(coordinate_to_notation((4, 5)), "f5");
+        assert_eq!(coordinate_from_notation("F5"), Some((4, 5)));
+    }
+
+    #[test]
+    fn notation_out_of_range_is_rejected() {
+        for notation in &["", "a", "i1", "a0", "a9", "a10", "5f"] {
+            assert_eq!(coordinate_from_notation(notation), None, "{}", notation);
+        }
+    }
+
+    #[test]
+    fn position_round_trips() {
+        let mut board = Board::new();
+        assert_eq!(Board::from_position(&board.to_position()).unwrap(), board);
+
+        for notation in &["f5", "d6", "c3"] {
+            let co = coordinate_from_notation(notation).unwrap();
+            board.update(Choice::Coordinate(co)).unwrap();
+        }
+        assert_eq!(board.player, Player::White);
+        assert_eq!(Board::from_position(&board.to_position()).unwrap(), board);
+    }
+
+    #[test]
+    fn invalid_positions_are_rejected() {
+        let squares = "-".repeat(64);
+
+        for position in &[
+            String::new(),
+            "X".repeat(63),
+            format!("{} red", squares),
+            format!("{}Z black", "-".repeat(63)),
+            format!("{} black extra", squares),
+        ] {
+            assert!(
+                matches!(Board::from_position(position), Err(Error::Parse(_))),
+                "{}",
+                position
+            );
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use crate::error::{Error, Result};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// 一時ファイルに書き込んでからリネームすることで、途中で落ちても元のファイルを壊さない
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+/// "tournament_latest.json"の世代番号付きのファイル名"tournament_latest_gen25.json"
+pub fn generation_path(path: &Path, generation: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}_gen{}.{}", stem, generation, ext.to_string_lossy()),
+        None => format!("{}_gen{}", stem, generation),
+    };
+
+    path.with_file_name(name)
+}
+
+/// 保存されている世代番号付きのファイルを新しい順に返す
+pub fn generation_paths(path: &Path) -> Vec<(usize, PathBuf)> {
+    let dir = match path.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    let prefix = format!("{}_gen", path.file_stem().unwrap_or_default().to_string_lossy());
+    let suffix = match path.extension() {
+        Some(ext) => format!(".{}", ext.to_string_lossy()),
+        None => String::new(),
+    };
+
+    let mut paths = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let generation = name
+                .strip_prefix(&prefix)?
+                .strip_suffix(&suffix)?
+                .parse::<usize>()
+                .ok()?;
+            Some((generation, path.with_file_name(name)))
+        })
+        .collect::<Vec<_>>();
+
+    paths.sort_by_key(|(generation, _path)| std::cmp::Reverse(*generation));
+    paths
+}
+
+/// 世代番号付きのファイルと最新のファイルを書き込み、古い世代番号付きのファイルを`keep_num`個まで減らす
+pub fn save_checkpoint(
+    path: &Path,
+    generation: usize,
+    bytes: &[u8],
+    keep_num: usize,
+) -> io::Result<()> {
+    write_atomic(&generation_path(path, generation), bytes)?;
+    write_atomic(path, bytes)?;
+
+    for (_generation, old) in generation_paths(path).into_iter().skip(keep_num.max(1)) {
+        fs::remove_file(old)?;
+    }
+
+    Ok(())
+}
+
+/// 最新のファイル、世代番号付きのファイルの新しい順に読み込みを試し、最初に読めたものを返す
+/// 壊れているファイルは警告を出して読み飛ばす
+pub fn load_checkpoint<T>(path: &Path, parse: impl Fn(&[u8]) -> Result<T>) -> Result<T> {
+    let mut candidates = vec![path.to_path_buf()];
+    candidates.extend(generation_paths(path).into_iter().map(|(_generation, path)| path));
+
+    let mut last_error = Error::NoCheckpoint(path.to_path_buf());
+
+    for candidate in candidates {
+        let result = fs::read(&candidate)
+            .map_err(Error::from)
+            .and_then(|bytes| parse(&bytes));
+
+        match result {
+            Ok(value) => return Ok(value),
+            Err(Error::Io(e)) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                eprintln!("Skip checkpoint {}: {}", candidate.display(), e);
+                last_error = e;
+            }
+        }
+    }
+
+    Err(last_error)
+}
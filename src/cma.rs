@@ -0,0 +1,241 @@
+use crate::cpu::{CPU, WEIGHT_LEN};
+use rand::Rng;
+use std::f64::consts::PI;
+
+/// CPUの重みの次元数
+pub const DIM: usize = WEIGHT_LEN * 4;
+
+/// CMA-ESの分布の状態
+/// 重みは序盤から順に各段階の重みを並べたベクトルとして扱う
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct CmaState {
+    /// 分布の平均
+    pub mean: Vec<f64>,
+    /// ステップサイズ
+    pub sigma: f64,
+    /// 共分散行列
+    pub covariance: Vec<Vec<f64>>,
+    /// ステップサイズの進化パス
+    pub path_sigma: Vec<f64>,
+    /// 共分散行列の進化パス
+    pub path_c: Vec<f64>,
+    /// 更新した回数
+    pub updates: usize,
+}
+
+/// 標準正規分布に従う乱数(Box-Muller法)
+fn standard_normal(rng: &mut impl Rng) -> f64 {
+    let u = 1.0 - rng.gen::<f64>();
+    let v = rng.gen::<f64>();
+    (-2.0 * u.ln()).sqrt() * (2.0 * PI * v).cos()
+}
+
+/// 対称行列をJacobi法で固有値分解し、(固有値, 固有ベクトルを列に並べた行列)を返す
+pub fn jacobi_eigen(matrix: &[Vec<f64>]) -> (Vec<f64>, Vec<Vec<f64>>) {
+    let n = matrix.len();
+    let mut a = matrix.to_vec();
+    let mut v = vec![vec![0.0; n]; n];
+    for (i, row) in v.iter_mut().enumerate() {
+        row[i] = 1.0;
+    }
+
+    for _sweep in 0..100 {
+        let off_diagonal = (0..n)
+            .flat_map(|i| (0..n).filter(move |&j| j != i).map(move |j| (i, j)))
+            .map(|(i, j)| a[i][j] * a[i][j])
+            .sum::<f64>();
+        if off_diagonal < 1e-20 {
+            break;
+        }
+
+        for p in 0..n {
+            for q in p + 1..n {
+                if a[p][q].abs() < 1e-30 {
+                    continue;
+                }
+
+                // a[p][q]を0にする回転
+                let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let t = if theta == 0.0 { 1.0 } else { t };
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+
+                for row in a.iter_mut() {
+                    let (akp, akq) = (row[p], row[q]);
+                    row[p] = c * akp - s * akq;
+                    row[q] = s * akp + c * akq;
+                }
+                let (row_p, row_q) = (a[p].clone(), a[q].clone());
+                for (k, (apk, aqk)) in row_p.iter().zip(&row_q).enumerate() {
+                    a[p][k] = c * apk - s * aqk;
+                    a[q][k] = s * apk + c * aqk;
+                }
+                for row in v.iter_mut() {
+                    let (vkp, vkq) = (row[p], row[q]);
+                    row[p] = c * vkp - s * vkq;
+                    row[q] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+
+    ((0..n).map(|i| a[i][i]).collect(), v)
+}
+
+/// 重みのベクトルをi8に丸めてCPUにする
+pub fn to_cpu(x: &[f64]) -> CPU {
+    let mut stages = [[0; WEIGHT_LEN]; 4];
+    for (stage, weights) in stages.iter_mut().enumerate() {
+        for (k, weight) in weights.iter_mut().enumerate() {
+            *weight = x[stage * WEIGHT_LEN + k].round().clamp(-128.0, 127.0) as i8;
+        }
+    }
+
+    CPU::from_stages(stages)
+}
+
+/// CPUの重みをベクトルにする
+pub fn from_cpu(cpu: &CPU) -> Vec<f64> {
+    cpu.stages()
+        .iter()
+        .flat_map(|weights| weights.iter().map(|&weight| weight as f64))
+        .collect()
+}
+
+impl CmaState {
+    /// 平均`mean`、ステップサイズ`sigma`、共分散行列を単位行列として始める
+    pub fn new(mean: Vec<f64>, sigma: f64) -> Self {
+        let n = mean.len();
+        let mut covariance = vec![vec![0.0; n]; n];
+        for (i, row) in covariance.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+
+        Self {
+            mean,
+            sigma,
+            covariance,
+            path_sigma: vec![0.0; n],
+            path_c: vec![0.0; n],
+            updates: 0,
+        }
+    }
+
+    /// 共分散行列の固有値分解から、(B D, B D^-1 B^T)を求める
+    fn decompose(&self) -> (Vec<Vec<f64>>, Vec<Vec<f64>>) {
+        let n = self.mean.len();
+        let (values, vectors) = jacobi_eigen(&self.covariance);
+        let d = values
+            .iter()
+            .map(|&value| value.max(1e-20).sqrt())
+            .collect::<Vec<_>>();
+
+        let mut bd = vec![vec![0.0; n]; n];
+        let mut inv_sqrt = vec![vec![0.0; n]; n];
+        for i in 0..n {
+            for j in 0..n {
+                bd[i][j] = vectors[i][j] * d[j];
+                inv_sqrt[i][j] = (0..n)
+                    .map(|k| vectors[i][k] * vectors[j][k] / d[k])
+                    .sum::<f64>();
+            }
+        }
+
+        (bd, inv_sqrt)
+    }
+
+    /// 分布から`num`個の重みのベクトルを作る
+    pub fn sample(&self, num: usize, rng: &mut impl Rng) -> Vec<Vec<f64>> {
+        let n = self.mean.len();
+        let (bd, _inv_sqrt) = self.decompose();
+
+        (0..num)
+            .map(|_| {
+                let z = (0..n).map(|_| standard_normal(rng)).collect::<Vec<_>>();
+                (0..n)
+                    .map(|i| {
+                        let y = (0..n).map(|j| bd[i][j] * z[j]).sum::<f64>();
+                        self.mean[i] + self.sigma * y
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// 良い順に並べた標本で分布を更新する
+    /// 上位半分を重み付きで使う
+    pub fn update(&mut self, sorted_samples: &[Vec<f64>]) {
+        let n = self.mean.len() as f64;
+        let mu = (sorted_samples.len() / 2).max(1);
+
+        let mut weights = (0..mu)
+            .map(|i| (mu as f64 + 0.5).ln() - (i as f64 + 1.0).ln())
+            .collect::<Vec<_>>();
+        let sum = weights.iter().sum::<f64>();
+        weights.iter_mut().for_each(|weight| *weight /= sum);
+        let mu_eff = 1.0 / weights.iter().map(|weight| weight * weight).sum::<f64>();
+
+        let cc = (4.0 + mu_eff / n) / (n + 4.0 + 2.0 * mu_eff / n);
+        let cs = (mu_eff + 2.0) / (n + mu_eff + 5.0);
+        let c1 = 2.0 / ((n + 1.3).powi(2) + mu_eff);
+        let cmu =
+            (1.0 - c1).min(2.0 * (mu_eff - 2.0 + 1.0 / mu_eff) / ((n + 2.0).powi(2) + mu_eff));
+        let damps = 1.0 + 2.0 * (((mu_eff - 1.0) / (n + 1.0)).sqrt() - 1.0).max(0.0) + cs;
+        let chi_n = n.sqrt() * (1.0 - 1.0 / (4.0 * n) + 1.0 / (21.0 * n * n));
+
+        let (_bd, inv_sqrt) = self.decompose();
+        let old_mean = self.mean.clone();
+        let ys = sorted_samples[..mu]
+            .iter()
+            .map(|x| {
+                x.iter()
+                    .zip(&old_mean)
+                    .map(|(x, m)| (x - m) / self.sigma)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let dim = self.mean.len();
+        let y_w = (0..dim)
+            .map(|i| ys.iter().zip(&weights).map(|(y, w)| w * y[i]).sum::<f64>())
+            .collect::<Vec<_>>();
+        for (mean, (old_mean, y_w)) in self.mean.iter_mut().zip(old_mean.iter().zip(&y_w)) {
+            *mean = old_mean + self.sigma * y_w;
+        }
+
+        // 進化パスの更新
+        let norm = (cs * (2.0 - cs) * mu_eff).sqrt();
+        for (path, row) in self.path_sigma.iter_mut().zip(&inv_sqrt) {
+            let c_inv_sqrt_y = row.iter().zip(&y_w).map(|(c, y)| c * y).sum::<f64>();
+            *path = (1.0 - cs) * *path + norm * c_inv_sqrt_y;
+        }
+        self.updates += 1;
+        let ps_norm = self.path_sigma.iter().map(|p| p * p).sum::<f64>().sqrt();
+        let h_sigma = ps_norm / (1.0 - (1.0 - cs).powi(2 * self.updates as i32)).sqrt() / chi_n
+            < 1.4 + 2.0 / (n + 1.0);
+        let h_sigma = if h_sigma { 1.0 } else { 0.0 };
+
+        let norm = (cc * (2.0 - cc) * mu_eff).sqrt();
+        for (path, y_w) in self.path_c.iter_mut().zip(&y_w) {
+            *path = (1.0 - cc) * *path + h_sigma * norm * y_w;
+        }
+
+        // 共分散行列の更新
+        for i in 0..dim {
+            for j in 0..dim {
+                let rank_one = self.path_c[i] * self.path_c[j]
+                    + (1.0 - h_sigma) * cc * (2.0 - cc) * self.covariance[i][j];
+                let rank_mu = ys
+                    .iter()
+                    .zip(&weights)
+                    .map(|(y, w)| w * y[i] * y[j])
+                    .sum::<f64>();
+                self.covariance[i][j] =
+                    (1.0 - c1 - cmu) * self.covariance[i][j] + c1 * rank_one + cmu * rank_mu;
+            }
+        }
+
+        self.sigma *= ((cs / damps) * (ps_norm / chi_n - 1.0)).exp();
+    }
+}
@@ -1,45 +1,258 @@
-use std::fs::File;
-use std::io::{Read, Write};
+use crate::checkpoint::write_atomic;
+use crate::cpu::Noise;
+use crate::difficulty::Difficulty;
+use crate::error::Result;
+use crate::schema::{migrate, Migration};
+use crate::tuner::TuneTarget;
+use serde_json::Value;
+use std::path::Path;
 
+/// `CONFIG_MIGRATIONS[i]`でバージョンiからi+1に移行する
+const CONFIG_MIGRATIONS: [Migration; Config::VERSION as usize] = [migrate_config_v0];
+
+/// バージョン0では`select_tournament_size`は使われず、常に8体のグループだった
+fn migrate_config_v0(value: &mut Value) {
+    if value["select_tournament_size"] == 4 {
+        value["select_tournament_size"] = Value::from(8);
+    }
+}
+
+/// 島ごとの遺伝的アルゴリズムの設定
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct IslandConfig {
+    pub mutate_prob: f64,
+    /// 交叉を行う確率(行わない場合は親をそのまま使う)
+    pub cross_prob: f64,
+}
+
+/// チェックポイントの保存形式
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub enum CheckpointFormat {
+    Json,
+    Binary,
+}
+
+/// 学習に使う最適化手法
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub enum Optimizer {
+    /// `Tournament::upgrade_generation`による遺伝的アルゴリズム
+    Genetic,
+    /// `Tournament::upgrade_generation_cma`によるCMA-ES
+    CmaEs,
+}
+
+/// ファイルに無い項目は`Config::new()`の値になる
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
+    /// 設定ファイルの形式のバージョン
+    pub version: u32,
     pub log_tournament_generation: usize,
+    /// 残しておく世代番号付きのトーナメントのファイルの数
+    pub checkpoint_keep_num: usize,
+    /// トーナメントの保存形式(読み込みはどちらの形式でもできる)
+    pub checkpoint_format: CheckpointFormat,
+    /// この世代まで更新したら学習を止める(TD学習では反復の回数)
+    pub max_generation: Option<usize>,
+    /// 学習を止めるまでの秒数
+    pub time_limit_secs: Option<u64>,
+    /// 乱数のシード(`None`なら起動ごとにランダムに決める)
+    pub seed: Option<u64>,
+    /// 1世代あたりのCPUの数
     pub tournament_size: usize,
+    pub optimizer: Optimizer,
+    /// CMA-ESで1世代に作るCPUの数
+    pub cma_population: usize,
+    /// CMA-ESの最初のステップサイズ
+    pub cma_sigma: f64,
+    /// 総当たり戦を行うグループの大きさ
     pub select_tournament_size: usize,
+    /// 世代更新に使うスレッド数
+    pub thread_num: usize,
+    /// 各グループで親となる上位CPUの数
+    pub parent_num: usize,
+    /// 各グループで突然変異させずにそのまま次世代に残す上位CPUの数
+    pub elite_num: usize,
+    /// 各グループで新たにランダム生成するCPUの数
+    pub random_num: usize,
+    /// 島ごとの設定(空なら全体を`mutate_prob`で常に交叉する1つの島とする)
+    pub islands: Vec<IslandConfig>,
+    /// 島の間で移住を行う世代の間隔
+    pub migration_interval: usize,
+    /// 各島から隣の島へ移住させるCPUの数
+    pub migration_num: usize,
+    /// 殿堂入りとして保存する過去の世代の優勝CPUの数
+    pub hall_of_fame_size: usize,
+    /// 各CPUが対局する殿堂入りCPUの数
+    pub hall_of_fame_opponent_num: usize,
     pub learning_depth: usize,
+    /// 学習の対局で手の選び方に加える乱雑さ
+    pub learning_noise: Noise,
+    /// 適応度評価で使う開始局面の数(各局面で先後を入れ替えて2局ずつ行う)
+    pub opening_num: usize,
+    /// 開始局面をランダムに進める手数
+    pub opening_random_plies: usize,
+    /// 開始局面に定石を使うか
+    pub use_opening_book: bool,
     pub simulation_depth: usize,
-    pub cross_prob: f64,
+    /// 人間と対局するCPUの手の選び方に加える乱雑さ
+    pub simulation_noise: Noise,
+    /// `simulate`で対局するMCTSの1手あたりの反復回数
+    pub simulation_mcts_iterations: usize,
+    /// 人間との対局の開始時に選べる難易度(選ばなければ`simulation_depth`と`simulation_noise`を使う)
+    pub difficulties: Vec<Difficulty>,
     pub mutate_prob: f64,
+    /// レーティングで保存済みのCPUが探索する深さ
+    pub rating_depth: usize,
+    /// レーティングの基準とする`CPU::new_alpha`の探索の深さ
+    pub rating_reference_depths: Vec<usize>,
+    /// レーティングの基準とするMCTSの1手あたりの反復回数
+    pub rating_mcts_iterations: Vec<usize>,
+    /// レーティングの1組あたりの開始局面の数
+    pub rating_opening_num: usize,
+    /// 何世代ごとに優勝CPUのベンチマークを指標に記録するか(0なら行わない)
+    pub metrics_benchmark_interval: usize,
+    /// ベンチマークで学習したCPUが探索する深さ
+    pub benchmark_depth: usize,
+    /// ベンチマークの対戦相手とする`CPU::new_alpha`の探索の深さ
+    pub benchmark_alpha_depths: Vec<usize>,
+    /// ベンチマークで対戦するMCTSの1手あたりの反復回数
+    pub benchmark_mcts_iterations: Vec<usize>,
+    /// ベンチマークの対戦相手1体あたりの開始局面の数
+    pub benchmark_opening_num: usize,
+    /// 自己対局の対局数
+    pub selfplay_game_num: usize,
+    /// 自己対局で手を選ぶ探索の深さ
+    pub selfplay_depth: usize,
+    /// 自己対局で探索結果の代わりにランダムな手を打つ確率
+    pub selfplay_random_prob: f64,
+    /// TD学習の1反復で自己対局する対局数
+    pub td_batch_games: usize,
+    /// TD学習の自己対局で手を選ぶ探索の深さ
+    pub td_depth: usize,
+    /// TD学習の自己対局でランダムな手を打つ確率
+    pub td_random_prob: f64,
+    /// TD(λ)のλ
+    pub td_lambda: f64,
+    pub td_learning_rate: f64,
+    /// TD学習で評価値を勝率に変換するときの尺度
+    pub td_scale: f64,
+    /// 何反復ごとにTD学習の途中経過を保存するか
+    pub td_log_interval: usize,
+    /// 重みの調整で何に合わせるか
+    pub tune_target: TuneTarget,
+    /// 重みの調整の反復回数
+    pub tune_epochs: usize,
+    /// 重みの調整で評価値を勝率に変換するときの尺度
+    pub tune_scale: f64,
+    pub tune_learning_rate: f64,
     pub tournament_latest_file_name: String,
     pub winner_latest_file_name: String,
+    pub rating_table_file_name: String,
+    pub metrics_file_name: String,
+    pub tuned_file_name: String,
+    pub selfplay_file_name: String,
+    pub td_latest_file_name: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Config {
+    /// 現在の設定ファイルの形式のバージョン
+    pub const VERSION: u32 = 1;
+
     pub fn new() -> Self {
         Self {
+            version: Self::VERSION,
             log_tournament_generation: 25,
+            checkpoint_keep_num: 3,
+            checkpoint_format: CheckpointFormat::Json,
+            max_generation: None,
+            time_limit_secs: None,
+            seed: None,
             tournament_size: 4096,
-            select_tournament_size: 4,
+            optimizer: Optimizer::Genetic,
+            cma_population: 16,
+            cma_sigma: 32.0,
+            select_tournament_size: 8,
+            thread_num: 64,
+            parent_num: 3,
+            elite_num: 1,
+            random_num: 1,
+            islands: Vec::new(),
+            migration_interval: 10,
+            migration_num: 2,
+            hall_of_fame_size: 16,
+            hall_of_fame_opponent_num: 2,
             learning_depth: 1,
+            learning_noise: Noise::default(),
+            opening_num: 2,
+            opening_random_plies: 2,
+            use_opening_book: true,
             simulation_depth: 8,
-            cross_prob: 0.75,
+            simulation_noise: Noise::default(),
+            simulation_mcts_iterations: 5000,
+            difficulties: Difficulty::presets(),
             mutate_prob: 0.025,
+            rating_depth: 3,
+            rating_reference_depths: vec![1, 3, 5],
+            rating_mcts_iterations: Vec::new(),
+            rating_opening_num: 4,
+            metrics_benchmark_interval: 5,
+            benchmark_depth: 1,
+            benchmark_alpha_depths: vec![1, 3],
+            benchmark_mcts_iterations: Vec::new(),
+            benchmark_opening_num: 4,
+            selfplay_game_num: 100,
+            selfplay_depth: 3,
+            selfplay_random_prob: 0.05,
+            td_batch_games: 64,
+            td_depth: 1,
+            td_random_prob: 0.1,
+            td_lambda: 0.7,
+            td_learning_rate: 20.0,
+            td_scale: 100.0,
+            td_log_interval: 10,
+            tune_target: TuneTarget::Result,
+            tune_epochs: 500,
+            tune_scale: 100.0,
+            tune_learning_rate: 0.5,
             tournament_latest_file_name: "tournament_latest.json".to_string(),
             winner_latest_file_name: "winner_latest.json".to_string(),
+            rating_table_file_name: "rating_table.json".to_string(),
+            metrics_file_name: "metrics.jsonl".to_string(),
+            tuned_file_name: "tuned.json".to_string(),
+            selfplay_file_name: "selfplay.jsonl".to_string(),
+            td_latest_file_name: "td_latest.json".to_string(),
+        }
+    }
+
+    /// 各島の設定
+    pub fn island_configs(&self) -> Vec<IslandConfig> {
+        if self.islands.is_empty() {
+            vec![IslandConfig {
+                mutate_prob: self.mutate_prob,
+                cross_prob: 1.0,
+            }]
+        } else {
+            self.islands.clone()
         }
     }
 
-    pub fn log(&self, log_file: &mut File) -> std::io::Result<()> {
-        log_file.set_len(0)?;
+    pub fn log(&self, path: &Path) -> Result<()> {
         let json = serde_json::to_string(self)?;
-        log_file.write_all(json.as_bytes())?;
-        log_file.flush()
+        Ok(write_atomic(path, json.as_bytes())?)
     }
 
-    pub fn from_log_file(log_file: &mut File) -> std::io::Result<Self> {
-        let mut buf = String::new();
-        log_file.read_to_string(&mut buf)?;
-        let tournament = serde_json::from_str(&buf)?;
-        Ok(tournament)
+    /// 古いバージョンの設定ファイルは移行してから読み込む
+    pub fn from_log_file(path: &Path) -> Result<Self> {
+        let buf = std::fs::read_to_string(path)?;
+        let value = migrate(serde_json::from_str(&buf)?, "Config", &CONFIG_MIGRATIONS)?;
+        let config = serde_json::from_value(value)?;
+        Ok(config)
     }
 }
@@ -1,9 +1,33 @@
+use crate::binary::{decode_cpus, is_binary};
 use crate::board::{Board, Choice, Coordinate, JudgeResult, Player};
-use rand::Rng;
+use crate::error::{Error, Result};
+use crate::schema::{migrate, Migration};
+use rand::prelude::{SliceRandom, StdRng};
+use rand::{Rng, SeedableRng};
 use std::fs::File;
 use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+pub const WEIGHT_LEN: usize = 11;
+
+/// `CPU::choose_noisy`で手の選び方に加える乱雑さ(既定値では乱雑さ無し)
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Noise {
+    /// 0より大きければ各手の評価値をこの値で割ったソフトマックスで手を選ぶ
+    pub temperature: f64,
+    /// この確率で合法手からランダムに選ぶ
+    pub epsilon: f64,
+    /// `board.turn`がこの値以下の間は合法手からランダムに選ぶ
+    pub random_plies: usize,
+}
 
-const WEIGHT_LEN: usize = 11;
+impl Noise {
+    /// 乱雑さが無く`choose_best`と同じ手を選ぶか
+    pub fn is_none(&self) -> bool {
+        self.temperature <= 0.0 && self.epsilon <= 0.0 && self.random_plies == 0
+    }
+}
 
 #[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
 pub struct CPU {
@@ -13,21 +37,79 @@ pub struct CPU {
     pub stage4: [i8; WEIGHT_LEN],
 }
 
+/// `CPU_MIGRATIONS[i]`でバージョンiからi+1に移行する
+/// バージョン0はversionが無いだけで重みの形式は同じ
+const CPU_MIGRATIONS: [Migration; CPU::VERSION as usize] = [|_| ()];
+
+/// バージョン付きで書き出すためのCPU
+#[derive(Serialize)]
+struct VersionedCPU<'a> {
+    version: u32,
+    #[serde(flatten)]
+    cpu: &'a CPU,
+}
+
 impl CPU {
+    /// 現在のCPUのJSONの形式のバージョン
+    pub const VERSION: u32 = 1;
+
+    /// バージョン付きの1行のJSONにする
+    pub fn to_json(&self) -> Result<String> {
+        let versioned = VersionedCPU {
+            version: Self::VERSION,
+            cpu: self,
+        };
+        Ok(serde_json::to_string(&versioned)?)
+    }
+
+    /// 古いバージョンのJSONは移行してから読み込む
+    pub fn from_json(str: &str) -> Result<Self> {
+        let value = Self::migrate_json(serde_json::from_str(str)?)?;
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// CPUのJSONを現在のバージョンに移行する
+    pub fn migrate_json(value: serde_json::Value) -> Result<serde_json::Value> {
+        migrate(value, "CPU", &CPU_MIGRATIONS)
+    }
+
     /// ファイルに改行を加えて出力
-    pub fn log(&self, log_file: &mut File) -> std::io::Result<()> {
-        let str = serde_json::to_string(self)?;
+    pub fn log(&self, log_file: &mut File) -> Result<()> {
+        let str = self.to_json()?;
         log_file.write_all(format!("\n{}", str).as_bytes())?;
-        log_file.flush()
+        Ok(log_file.flush()?)
     }
 
     /// ファイルから文字列として入力を受け取り、最後の行をパースして返す
-    pub fn from_log_file(log_file: &mut File) -> std::io::Result<Self> {
-        let mut buf = String::new();
-        log_file.read_to_string(&mut buf)?;
+    /// バイナリ形式なら最後のCPUを返す
+    pub fn from_log_file(log_file: &mut File) -> Result<Self> {
+        let mut bytes = Vec::new();
+        log_file.read_to_end(&mut bytes)?;
+
+        if is_binary(&bytes) {
+            return decode_cpus(&bytes)?
+                .pop()
+                .ok_or_else(|| Error::Parse("No CPU in file!".to_string()));
+        }
+
+        let buf = String::from_utf8_lossy(&bytes);
         let buf = buf.lines().last().unwrap_or("Invalid"); // "Invalid"ならパースに失敗するので
-        let cpu = serde_json::from_str(&buf)?;
-        Ok(cpu)
+        Self::from_json(buf)
+    }
+
+    /// 序盤から順に各段階の重み
+    pub fn stages(&self) -> [[i8; WEIGHT_LEN]; 4] {
+        [self.stage1, self.stage2, self.stage3, self.stage4]
+    }
+
+    /// 序盤から順の各段階の重みからCPUを作る
+    pub fn from_stages(stages: [[i8; WEIGHT_LEN]; 4]) -> Self {
+        Self {
+            stage1: stages[0],
+            stage2: stages[1],
+            stage3: stages[2],
+            stage4: stages[3],
+        }
     }
 
     /// 乱数生成器を受け取り、ランダムに値を決めたCPUをつくる
@@ -65,8 +147,22 @@ impl CPU {
         }
     }
 
-    /// 盤面を次の手の人視点で評価
-    pub fn eval_board(&self, board: &Board) -> isize {
+    /// 盤面の段階(序盤から0から3)
+    pub fn stage(board: &Board) -> usize {
+        if board.turn < 15 {
+            0
+        } else if board.turn < 30 {
+            1
+        } else if board.turn < 45 {
+            2
+        } else {
+            3
+        }
+    }
+
+    /// 盤面の特徴量を次の手の人視点で返す
+    /// 各重みに対応するマスの自分と敵の駒の数の差と、次に打てる手の数
+    pub fn features(board: &Board) -> [isize; WEIGHT_LEN] {
         fn mirror(i: usize) -> usize {
             match i {
                 0..=3 => i,
@@ -92,50 +188,65 @@ impl CPU {
             }
         }
 
-        let weights = if board.turn < 15 {
-            self.stage1
-        } else if board.turn < 30 {
-            self.stage2
-        } else if board.turn < 45 {
-            self.stage3
-        } else {
-            self.stage4
-        };
-
-        let mut score = 0;
+        let mut features = [0; WEIGHT_LEN];
 
         for k in 0..64 {
             let (i, j) = (k / 8, k % 8);
             let index = co_to_index((i, j));
 
             // 自分の駒なら加点
-            score += match board.player_board & 1 << (63 - k) {
-                0 => 0,
-                _ => weights[index] as isize,
-            };
+            if board.player_board & 1 << (63 - k) != 0 {
+                features[index] += 1;
+            }
 
             // 敵の駒なら減点
-            score -= match board.opponent_board & 1 << (63 - k) {
-                0 => 0,
-                _ => weights[index] as isize,
-            };
+            if board.opponent_board & 1 << (63 - k) != 0 {
+                features[index] -= 1;
+            }
         }
 
-        // 次に打てる手の数に応じて加点
-        score += board.make_legal_board().count_ones() as isize * weights[10] as isize;
+        // 次に打てる手の数
+        features[10] = board.make_legal_board().count_ones() as isize;
+
+        features
+    }
+
+    /// 盤面を次の手の人視点で評価
+    pub fn eval_board(&self, board: &Board) -> isize {
+        let weights = self.stages()[Self::stage(board)];
 
-        score
+        Self::features(board)
+            .iter()
+            .zip(weights.iter())
+            .map(|(&feature, &weight)| feature * weight as isize)
+            .sum()
     }
 
     /// Nodeを次の人の手視点で評価
     pub fn eval_node(&self, board: &Board, depth: usize, alpha: isize) -> isize {
+        self.eval_node_until(board, depth, alpha, None).unwrap()
+    }
+
+    /// `eval_node`と同じく評価し、`deadline`を過ぎたら探索を打ち切って`None`を返す
+    fn eval_node_until(
+        &self,
+        board: &Board,
+        depth: usize,
+        alpha: isize,
+        deadline: Option<Instant>,
+    ) -> Option<isize> {
         if depth == 0 {
-            return self.eval_board(board);
+            return Some(self.eval_board(board));
+        }
+
+        // 葉に近いNodeでは時刻を確かめない
+        if depth >= 2 && matches!(deadline, Some(deadline) if Instant::now() >= deadline) {
+            return None;
         }
 
         if board.is_skip() {
             let mut board_clone = board.clone();
-            return match board_clone.update(Choice::Skip).unwrap() {
+            return Some(match board_clone.update(Choice::Skip).unwrap() {
                 JudgeResult::Win(winner) => {
                     if winner == board.player {
                         1 << 60
@@ -144,8 +255,10 @@ impl CPU {
                     }
                 }
                 JudgeResult::Draw => 0,
-                JudgeResult::Continue => -self.eval_node(&board_clone, depth - 1, -(1 << 62)),
-            };
+                JudgeResult::Continue => {
+                    -self.eval_node_until(&board_clone, depth - 1, -(1 << 62), deadline)?
+                }
+            });
         }
 
         let legal = board.make_legal_board();
@@ -163,13 +276,14 @@ impl CPU {
                     }
                     JudgeResult::Win(winner) => {
                         if winner == board.player {
-                            return 1 << 60;
+                            return Some(1 << 60);
                         } else {
                             max_score = (max_score).max(-(1 << 60));
                         }
                     }
                     JudgeResult::Continue => {
-                        let opponent_score = self.eval_node(&board_clone, depth - 1, max_score);
+                        let opponent_score =
+                            self.eval_node_until(&board_clone, depth - 1, max_score, deadline)?;
 
                         max_score = (max_score).max(-opponent_score);
                     }
@@ -177,18 +291,24 @@ impl CPU {
 
                 // すでに確定した最低点より低い点数を含むNodeは枝刈り
                 if alpha >= -max_score {
-                    return max_score;
+                    return Some(max_score);
                 }
             }
         }
 
-        max_score
+        Some(max_score)
     }
 
     /// 次の一手として最適なものを選ぶ
     pub fn choose_best(&self, board: &Board, depth: usize) -> Choice {
+        self.choose_best_with_score(board, depth).0
+    }
+
+    /// 次の一手として最適なものと、その手を打った場合の次の手の人視点の評価値を返す
+    /// `depth`が0の場合は打った後の盤面をそのまま評価する
+    pub fn choose_best_with_score(&self, board: &Board, depth: usize) -> (Choice, isize) {
         if board.is_skip() {
-            return Choice::Skip;
+            return (Choice::Skip, self.eval_node(board, depth, -(1 << 62)));
         }
 
         let legal = board.make_legal_board();
@@ -210,7 +330,7 @@ impl CPU {
                     }
                     JudgeResult::Win(winner) => {
                         if winner == board.player {
-                            return choice;
+                            return (choice, 1 << 60);
                         }
 
                         if max_score < -(1 << 60) {
@@ -219,7 +339,8 @@ impl CPU {
                         }
                     }
                     JudgeResult::Continue => {
-                        let next_score = -self.eval_node(&board_clone, depth - 1, max_score);
+                        let next_score =
+                            -self.eval_node(&board_clone, depth.saturating_sub(1), max_score);
 
                         if max_score < next_score {
                             max_score = next_score;
@@ -230,14 +351,158 @@ impl CPU {
             }
         }
 
-        best_choice
+        (best_choice, max_score)
+    }
+
+    /// `eval_choices_within`で評価し、最善手とその深さを返す
+    pub fn choose_best_within(
+        &self,
+        board: &Board,
+        max_depth: usize,
+        time_limit: Duration,
+    ) -> (Choice, usize) {
+        let (choices, depth) = self.eval_choices_within(board, max_depth, time_limit);
+        (Self::best_choice(&choices), depth)
+    }
+
+    /// 評価値が最大の手(同点なら先の手、合法手が無ければパス)
+    pub fn best_choice(choices: &[(Choice, isize)]) -> Choice {
+        choices
+            .iter()
+            .fold((Choice::Skip, -(1 << 62)), |best, &choice| {
+                if best.1 < choice.1 {
+                    choice
+                } else {
+                    best
+                }
+            })
+            .0
+    }
+
+    /// 深さ1から順に`max_depth`まで各合法手を評価し、`time_limit`を使い切りそうになったらそこで止める
+    /// 次の深さには今の深さの数倍の時間がかかるため、経過時間が`time_limit`の4分の1を超えたら止める
+    /// 探索中に`time_limit`を過ぎたらその深さは捨てる(深さ1は必ず最後まで探索する)
+    /// 最後に探索し終えた深さの評価値とその深さを返す
+    pub fn eval_choices_within(
+        &self,
+        board: &Board,
+        max_depth: usize,
+        time_limit: Duration,
+    ) -> (Vec<(Choice, isize)>, usize) {
+        let start = Instant::now();
+        let deadline = start + time_limit;
+        let mut choices = self.eval_choices(board, 1);
+        let mut depth = 1;
+
+        while depth < max_depth && start.elapsed() * 4 < time_limit {
+            match self.eval_choices_until(board, depth + 1, Some(deadline)) {
+                Some(deeper) => choices = deeper,
+                None => break,
+            }
+            depth += 1;
+        }
+
+        (choices, depth)
+    }
+
+    /// 各合法手と、その手を打った場合の次の手の人視点の評価値
+    /// `depth`が0の場合は打った後の盤面をそのまま評価する
+    pub fn eval_choices(&self, board: &Board, depth: usize) -> Vec<(Choice, isize)> {
+        self.eval_choices_until(board, depth, None).unwrap()
+    }
+
+    /// `eval_choices`と同じく評価し、`deadline`を過ぎたら探索を打ち切って`None`を返す
+    fn eval_choices_until(
+        &self,
+        board: &Board,
+        depth: usize,
+        deadline: Option<Instant>,
+    ) -> Option<Vec<(Choice, isize)>> {
+        board
+            .legal_choices()
+            .into_iter()
+            .map(|choice| {
+                let mut board_clone = board.clone();
+                let score = match board_clone.update(choice).unwrap() {
+                    JudgeResult::Draw => 0,
+                    JudgeResult::Win(winner) if winner == board.player => 1 << 60,
+                    JudgeResult::Win(_) => -(1 << 60),
+                    JudgeResult::Continue => -self.eval_node_until(
+                        &board_clone,
+                        depth.saturating_sub(1),
+                        -(1 << 62),
+                        deadline,
+                    )?,
+                };
+                Some((choice, score))
+            })
+            .collect()
+    }
+
+    /// `noise`に従って乱雑さを加えて次の一手を選ぶ
+    /// 乱雑さが無い場合は乱数を使わずに`choose_best`と同じ手を選ぶ
+    pub fn choose_noisy(
+        &self,
+        board: &Board,
+        depth: usize,
+        noise: &Noise,
+        rng: &mut impl Rng,
+    ) -> Choice {
+        if board.is_skip() {
+            return Choice::Skip;
+        }
+
+        if let Some(choice) = Self::choose_random(board, noise, rng) {
+            return choice;
+        }
+
+        if noise.temperature <= 0.0 {
+            return self.choose_best(board, depth);
+        }
+
+        Self::sample_choice(&self.eval_choices(board, depth), noise.temperature, rng)
+    }
+
+    /// `noise`に従って合法手からランダムに選ぶ場合はその手を返す
+    pub fn choose_random(board: &Board, noise: &Noise, rng: &mut impl Rng) -> Option<Choice> {
+        if board.turn <= noise.random_plies
+            || (noise.epsilon > 0.0 && rng.gen::<f64>() < noise.epsilon)
+        {
+            return board.legal_choices().choose(rng).copied();
+        }
+
+        None
+    }
+
+    /// `eval_choices`の評価値をもとに`temperature`のソフトマックスで手を選ぶ
+    pub fn sample_choice(
+        choices: &[(Choice, isize)],
+        temperature: f64,
+        rng: &mut impl Rng,
+    ) -> Choice {
+        // 最大の評価値を引いてから指数をとり、重みに比例した確率で選ぶ
+        let max_score = choices.iter().map(|&(_choice, score)| score).max().unwrap();
+        let weights = choices
+            .iter()
+            .map(|&(_choice, score)| ((score - max_score) as f64 / temperature).exp())
+            .collect::<Vec<_>>();
+
+        let mut rest = rng.gen::<f64>() * weights.iter().sum::<f64>();
+        for (&(choice, _score), weight) in choices.iter().zip(&weights) {
+            if rest < *weight {
+                return choice;
+            }
+            rest -= weight;
+        }
+
+        choices.last().unwrap().0
     }
 
     /// 遺伝子を一定の確率にランダムで乱数にする
     pub fn mutate_cpu(&mut self, mutate_prob: f64, rng: &mut impl Rng) {
         fn mutate(weights: &mut [i8], mutate_prob: f64, rng: &mut impl Rng) {
             for weight in weights {
-                if mutate_prob < rng.gen::<f64>() {
+                if rng.gen::<f64>() < mutate_prob {
                     *weight = rng.gen();
                 }
             }
@@ -250,29 +515,157 @@ impl CPU {
     }
 }
 
-/// 2つのCPUのうち優秀な方を返す
-pub fn eval_cpu<'a>(black: &'a CPU, white: &'a CPU, depth: usize) -> &'a CPU {
-    let mut board = Board::new();
-    let mut winner = black;
+/// 対局結果の集計
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MatchResult {
+    pub win: usize,
+    pub draw: usize,
+    pub lose: usize,
+    /// 石数差の合計
+    pub disc_diff: isize,
+}
+
+impl MatchResult {
+    /// 勝ちを2点、引き分けを1点とした勝ち点
+    pub fn points(&self) -> usize {
+        self.win * 2 + self.draw
+    }
+
+    pub fn games(&self) -> usize {
+        self.win + self.draw + self.lose
+    }
+
+    /// 対戦相手から見た結果
+    pub fn reversed(&self) -> Self {
+        Self {
+            win: self.lose,
+            draw: self.draw,
+            lose: self.win,
+            disc_diff: -self.disc_diff,
+        }
+    }
+
+    /// `me`から見た1局分の結果を加える
+    pub fn add_game(&mut self, result: JudgeResult, me: Player, board: &Board) {
+        match result {
+            JudgeResult::Win(winner) if winner == me => self.win += 1,
+            JudgeResult::Win(_) => self.lose += 1,
+            _ => self.draw += 1,
+        }
+
+        let (player, opponent) = board.calc_now_score();
+        let diff = player as isize - opponent as isize;
+        self.disc_diff += if board.player == me { diff } else { -diff };
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        self.win += other.win;
+        self.draw += other.draw;
+        self.lose += other.lose;
+        self.disc_diff += other.disc_diff;
+    }
+}
+
+/// 盤面から終局まで対局し、結果と終局時の盤面を返す
+pub fn play_game(black: &CPU, white: &CPU, depth: usize, board: &Board) -> (JudgeResult, Board) {
+    play_game_by(
+        |board| black.choose_best(board, depth),
+        |board| white.choose_best(board, depth),
+        board,
+    )
+}
+
+/// 手を選ぶ関数同士で盤面から終局まで対局し、結果と終局時の盤面を返す
+pub fn play_game_by(
+    mut black: impl FnMut(&Board) -> Choice,
+    mut white: impl FnMut(&Board) -> Choice,
+    board: &Board,
+) -> (JudgeResult, Board) {
+    let mut board = board.clone();
 
     loop {
         let next = if board.player == Player::Black {
-            black.choose_best(&board, depth)
+            black(&board)
         } else {
-            white.choose_best(&board, depth)
+            white(&board)
         };
 
         match board.update(next).unwrap() {
             JudgeResult::Continue => continue,
-            JudgeResult::Win(w) => {
-                winner = if w == Player::Black { black } else { white };
-                break;
-            }
-            _ => break,
+            result => return (result, board),
         }
     }
+}
+
+/// 各開始局面から先後を入れ替えて2局ずつ対局し、`left`から見た結果を返す
+pub fn eval_match(left: &CPU, right: &CPU, depth: usize, openings: &[Board]) -> MatchResult {
+    eval_match_by(
+        |board| left.choose_best(board, depth),
+        |board| right.choose_best(board, depth),
+        openings,
+    )
+}
 
-    winner
+/// `eval_match`の対局で両者に同じ`noise`を加える
+/// 乱数生成器は`rng`から`left`用と`right`用を作る
+pub fn eval_match_noisy(
+    left: &CPU,
+    right: &CPU,
+    depth: usize,
+    noise: &Noise,
+    openings: &[Board],
+    rng: &mut impl Rng,
+) -> MatchResult {
+    let mut left_rng = StdRng::seed_from_u64(rng.gen());
+    let mut right_rng = StdRng::seed_from_u64(rng.gen());
+
+    eval_match_by(
+        |board| left.choose_noisy(board, depth, noise, &mut left_rng),
+        |board| right.choose_noisy(board, depth, noise, &mut right_rng),
+        openings,
+    )
+}
+
+/// 手を選ぶ関数同士で`eval_match`と同じ対局を行う
+pub fn eval_match_by(
+    mut left: impl FnMut(&Board) -> Choice,
+    mut right: impl FnMut(&Board) -> Choice,
+    openings: &[Board],
+) -> MatchResult {
+    let mut result = MatchResult::default();
+
+    for opening in openings {
+        let me = opening.player;
+
+        let (judge, board) = if me == Player::Black {
+            play_game_by(&mut left, &mut right, opening)
+        } else {
+            play_game_by(&mut right, &mut left, opening)
+        };
+        result.add_game(judge, me, &board);
+
+        let (judge, board) = if me == Player::Black {
+            play_game_by(&mut right, &mut left, opening)
+        } else {
+            play_game_by(&mut left, &mut right, opening)
+        };
+        result.add_game(judge, me.next(), &board);
+    }
+
+    result
+}
+
+/// 2つのCPUのうち優秀な方を返す
+/// 初期盤面から先後を入れ替えて2局行い、勝ち点、石数差の順に比べる
+/// 完全に互角の場合は`left`を返す
+pub fn eval_cpu<'a>(left: &'a CPU, right: &'a CPU, depth: usize) -> &'a CPU {
+    let result = eval_match(left, right, depth, &[Board::new()]);
+
+    if (result.win, result.disc_diff) >= (result.lose, 0) {
+        left
+    } else {
+        right
+    }
 }
 
 /// ランダムな2点で遺伝子を入れ替えたCPUを作成する
@@ -340,3 +733,20 @@ pub fn random_cross(left: &CPU, right: &CPU, rng: &mut impl Rng) -> CPU {
         stage4,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn choose_best_with_depth_0_evaluates_boards_after_each_move() {
+        let cpu = CPU::new_alpha();
+        let board = Board::new();
+
+        assert_eq!(
+            cpu.choose_best_with_score(&board, 0),
+            cpu.choose_best_with_score(&board, 1)
+        );
+        assert_eq!(cpu.eval_choices(&board, 0), cpu.eval_choices(&board, 1));
+    }
+}
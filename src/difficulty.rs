@@ -0,0 +1,79 @@
+use crate::cpu::Noise;
+
+/// 人間と対局するCPUの強さの設定
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Difficulty {
+    pub name: String,
+    /// 探索の最大の深さ
+    pub depth: usize,
+    /// 1手あたりの思考時間(ミリ秒)。指定すると時間内に終わる深さまでしか読まない
+    #[serde(default)]
+    pub time_limit_ms: Option<u64>,
+    #[serde(default)]
+    pub noise: Noise,
+    /// 最善手以外の手をわざと選ぶ確率
+    #[serde(default)]
+    pub blunder_prob: f64,
+}
+
+impl Difficulty {
+    /// 弱い順の標準の難易度
+    pub fn presets() -> Vec<Self> {
+        let level = |name: &str, depth, time_limit_ms, noise, blunder_prob| Self {
+            name: name.to_string(),
+            depth,
+            time_limit_ms,
+            noise,
+            blunder_prob,
+        };
+
+        vec![
+            level(
+                "beginner",
+                1,
+                None,
+                Noise {
+                    temperature: 30.0,
+                    epsilon: 0.2,
+                    random_plies: 2,
+                },
+                0.2,
+            ),
+            level(
+                "easy",
+                2,
+                None,
+                Noise {
+                    temperature: 15.0,
+                    epsilon: 0.1,
+                    random_plies: 0,
+                },
+                0.1,
+            ),
+            level(
+                "normal",
+                4,
+                None,
+                Noise {
+                    temperature: 5.0,
+                    epsilon: 0.0,
+                    random_plies: 0,
+                },
+                0.03,
+            ),
+            level("hard", 6, Some(2000), Noise::default(), 0.0),
+            level("expert", 10, Some(5000), Noise::default(), 0.0),
+        ]
+    }
+
+    /// 名前か、1から始まる番号で難易度を探す
+    pub fn find<'a>(levels: &'a [Self], selector: &str) -> Option<&'a Self> {
+        let selector = selector.trim();
+        match selector.parse::<usize>() {
+            Ok(num) => levels.get(num.checked_sub(1)?),
+            Err(_) => levels
+                .iter()
+                .find(|level| level.name.eq_ignore_ascii_case(selector)),
+        }
+    }
+}
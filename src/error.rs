@@ -0,0 +1,68 @@
+use crate::board::{coordinate_to_notation, Coordinate};
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::path::PathBuf;
+
+/// 盤面の操作とファイルの読み書きで起きるエラー
+#[derive(Debug)]
+pub enum Error {
+    /// 盤面の外の座標
+    OutOfBounds(Coordinate),
+    /// 石を置けない場所
+    IllegalMove(Coordinate),
+    /// 打てる場所があるのにパスした
+    IllegalPass,
+    /// 終局した盤面に手を打った
+    GameOver,
+    /// 局面、棋譜、バイナリ形式、JSONのバージョンなどが読めない
+    Parse(String),
+    /// 最新のファイルも世代番号付きのファイルも無い
+    NoCheckpoint(PathBuf),
+    /// 外部エンジンを起動できない、または応答が途切れた
+    Engine(String),
+    Io(io::Error),
+    /// JSONの変換に失敗した
+    Serialization(serde_json::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::OutOfBounds((i, j)) => write!(f, "Out of index: ({}, {})", i, j),
+            Error::IllegalMove(co) => {
+                write!(f, "Impossible choice: {}", coordinate_to_notation(*co))
+            }
+            Error::IllegalPass => write!(f, "Illegal pass: there are legal moves"),
+            Error::GameOver => write!(f, "Game is already over"),
+            Error::Parse(message) => write!(f, "{}", message),
+            Error::NoCheckpoint(path) => write!(f, "No checkpoint found: {}", path.display()),
+            Error::Engine(message) => write!(f, "{}", message),
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Serialization(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Serialization(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Serialization(e)
+    }
+}
@@ -3,7 +3,24 @@ extern crate serde_derive;
 extern crate serde;
 extern crate serde_json;
 
+pub mod agent;
+pub mod archive;
+pub mod binary;
 pub mod board;
+pub mod checkpoint;
+pub mod cma;
 pub mod cpu;
+pub mod difficulty;
+pub mod error;
+pub mod mcts;
+pub mod metrics;
+pub mod opening;
+pub mod rating;
+pub mod reference;
+pub mod schema;
+pub mod selfplay;
+pub mod stop;
+pub mod td;
 pub mod config;
 pub mod tournament;
+pub mod tuner;
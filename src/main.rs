@@ -1,156 +1,679 @@
-use bit_othello::board::{Board, Choice, JudgeResult, Player};
-use bit_othello::config::Config;
-use bit_othello::cpu::{eval_cpu, CPU};
+use bit_othello::agent::{play_agents, Agent, CpuAgent, ExternalAgent, HumanAgent, ReferenceAgent};
+use bit_othello::archive::{Archive, ArchiveEntry};
+use bit_othello::binary::convert;
+use bit_othello::board::{Board, JudgeResult};
+use bit_othello::checkpoint::write_atomic;
+use bit_othello::config::{Config, Optimizer};
+use bit_othello::cpu::{eval_cpu, eval_match, CPU};
+use bit_othello::difficulty::Difficulty;
+use bit_othello::error::Error;
+use bit_othello::mcts::{Budget, Mcts, Playout};
+use bit_othello::metrics::GenerationMetrics;
+use bit_othello::opening::{board_from_moves, make_openings};
+use bit_othello::rating::{run_rating, RatedPlayer, RatingTable};
+use bit_othello::reference::{benchmark, Reference};
+use bit_othello::selfplay::selfplay;
+use bit_othello::stop::{install_stop_handler, StopCondition};
+use bit_othello::td::TdLearner;
 use bit_othello::tournament::Tournament;
-use rand::prelude::ThreadRng;
-use rand::Rng;
+use bit_othello::tuner::{load_dataset, Tuner};
+use rand::prelude::{StdRng, ThreadRng};
+use rand::{Rng, SeedableRng};
 use std::env::args;
 use std::fs::{File, OpenOptions};
 use std::io::stdin;
+use std::path::Path;
+use std::time::{Duration, Instant};
 
 const CONFIG_FILE_NAME: &str = "config.json";
+const ARCHIVE_USAGE: &str = "Usage: archive [list | compare <cpu> <cpu> | rollback <cpu>]";
 
 fn main() {
-    let mut config_file = OpenOptions::new()
-        .create(true)
-        .read(true)
-        .write(true)
-        .open(CONFIG_FILE_NAME)
-        .unwrap();
-
-    let config = Config::from_log_file(&mut config_file).unwrap_or(Config::new());
-    config.log(&mut config_file).unwrap();
+    let config_path = Path::new(CONFIG_FILE_NAME);
+    // 設定ファイルが読めない場合は上書きせずに止める
+    let config = if config_path.exists() {
+        Config::from_log_file(config_path).unwrap_or_else(|e| {
+            eprintln!("Failed to load {}: {}", CONFIG_FILE_NAME, e);
+            std::process::exit(1);
+        })
+    } else {
+        Config::new()
+    };
+    config.log(config_path).unwrap();
 
     let mut winner_latest_log_file = OpenOptions::new()
         .create(true)
         .append(true)
         .read(true)
-        .write(true)
-        .open(config.winner_latest_file_name)
+        .open(&config.winner_latest_file_name)
         .unwrap();
-    let mut tournament_latest_log_file = OpenOptions::new()
-        .create(true)
-        .read(true)
-        .write(true)
-        .open(config.tournament_latest_file_name)
-        .unwrap();
-    let mut rng = ThreadRng::default();
+    let seed = config.seed.unwrap_or_else(|| ThreadRng::default().gen());
+    let mut rng = StdRng::seed_from_u64(seed);
 
-    let arg = args().skip(1).next().expect("No args!");
+    let arg = args().nth(1).expect("No args!");
     match arg {
-        a if &a == "simulate" => simulate(&mut winner_latest_log_file, config.simulation_depth),
+        a if &a == "simulate" => simulate(
+            &mut winner_latest_log_file,
+            &args().skip(2).collect::<Vec<_>>(),
+            &config,
+            rng.gen(),
+        ),
         a if &a == "learn" => learn(
-            &mut tournament_latest_log_file,
+            Path::new(&config.tournament_latest_file_name),
+            &config,
+            seed,
+        ),
+        a if &a == "td" => learn_td(args().nth(2), &config, seed),
+        a if &a == "rate" => rate(&args().skip(2).collect::<Vec<_>>(), &config, &mut rng),
+        a if &a == "benchmark" => match args().nth(2) {
+            Some(file_name) => {
+                run_benchmark(&mut File::open(file_name).unwrap(), &config, &mut rng)
+            }
+            None => run_benchmark(&mut winner_latest_log_file, &config, &mut rng),
+        },
+        a if &a == "archive" => archive(
+            &mut winner_latest_log_file,
+            &args().skip(2).collect::<Vec<_>>(),
+            &config,
+            &mut rng,
+        ),
+        a if &a == "selfplay" => run_selfplay(
             &mut winner_latest_log_file,
-            config.log_tournament_generation,
-            config.tournament_size,
-            // config.select_tournament_size,
-            config.learning_depth,
-            // config.cross_prob,
-            config.mutate_prob,
+            &args().skip(2).collect::<Vec<_>>(),
+            &config,
             &mut rng,
         ),
+        a if &a == "tune" => tune(&args().nth(2).expect("No dataset file!"), &config),
+        a if &a == "convert" => {
+            let (input, output) = (args().nth(2), args().nth(3));
+            convert_file(
+                &input.expect("No input file!"),
+                &output.expect("No output file!"),
+            );
+        }
         _ => unimplemented!(),
     }
 }
 
-pub fn learn(
-    tournament_log_file: &mut File,
-    winner_latest_log_file: &mut File,
-    log_tournament_generation: usize,
-    tournament_size: usize,
-    // selection_size: usize,
-    learn_depth: usize,
-    // cross_prob: f64,
-    mutate_prob: f64,
-    rng: &mut impl Rng,
-) {
-    let tournament = Tournament::from_log_file(tournament_log_file);
-    let mut tournament = if let Err(e) = tournament {
+pub fn learn(tournament_log_path: &Path, config: &Config, seed: u64) {
+    let tournament = Tournament::from_log_file(tournament_log_path);
+    let (mut tournament, resumed) = if let Err(e) = tournament {
         eprintln!("{}", e);
-        Tournament::new_random(tournament_size, rng)
+        (Tournament::new_random(config.tournament_size, seed), false)
     } else {
-        tournament.unwrap()
+        (tournament.unwrap(), true)
     };
+    println!("Seed: {}", tournament.seed);
+
+    // 再開した場合は記録済みの世代の指標を重複して記録しない
+    let last_logged = if resumed {
+        GenerationMetrics::last_logged_generation(Path::new(&config.metrics_file_name))
+    } else {
+        None
+    };
+
+    let mut metrics_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&config.metrics_file_name)
+        .unwrap();
+    let references = Reference::suite(
+        &config.benchmark_alpha_depths,
+        &config.benchmark_mcts_iterations,
+    );
+
+    // Ctrl-Cでは今の世代を終えてから保存して止める
+    install_stop_handler();
+    let stop_condition = StopCondition::new(
+        config.max_generation,
+        config.time_limit_secs.map(Duration::from_secs),
+    );
+    let mut logged = true;
 
     loop {
+        if let Some(reason) = stop_condition.check(tournament.generation) {
+            if !logged {
+                log_learning(&tournament, tournament_log_path, config);
+            }
+            println!(
+                "{}: stopped before generation {}",
+                reason, tournament.generation
+            );
+            break;
+        }
+
         println!("Now generation: {}", tournament.generation);
-        tournament.upgrade_generation(mutate_prob, learn_depth, rng);
-
-        if tournament.generation % log_tournament_generation == 0 {
-            tournament.log(tournament_log_file).unwrap();
-            let mut iter = tournament.cpus.iter();
-            let first = iter.next().unwrap();
-            iter.fold(first, |a, b| eval_cpu(a, b, learn_depth))
-                .log(winner_latest_log_file)
+        let start = Instant::now();
+        let summary = match config.optimizer {
+            Optimizer::Genetic => tournament.upgrade_generation(config),
+            Optimizer::CmaEs => tournament.upgrade_generation_cma(config),
+        };
+        let wall_time = start.elapsed();
+        let generation = tournament.generation - 1;
+        let already_logged = match last_logged {
+            Some(last) => generation <= last,
+            None => false,
+        };
+
+        let reference_win_rate = if !already_logged
+            && config.metrics_benchmark_interval > 0
+            && generation % config.metrics_benchmark_interval == 0
+        {
+            let mut rng = StdRng::seed_from_u64(tournament.seed ^ tournament.generation as u64);
+            let openings = make_openings(
+                config.benchmark_opening_num,
+                config.opening_random_plies,
+                config.use_opening_book,
+                &mut rng,
+            );
+            let results = benchmark(
+                &summary.champion,
+                config.benchmark_depth,
+                &references,
+                &openings,
+                &mut rng,
+            );
+            let sum = results.iter().map(|result| result.win_rate()).sum::<f64>();
+            Some(sum / results.len() as f64)
+        } else {
+            None
+        };
+
+        if !already_logged {
+            GenerationMetrics::new(generation, &summary, wall_time, reference_win_rate)
+                .log(&mut metrics_file)
                 .unwrap();
         }
+
+        logged = tournament.generation % config.log_tournament_generation == 0;
+        if logged {
+            log_learning(&tournament, tournament_log_path, config);
+        }
     }
 }
 
-pub fn simulate(winner_log_file: &mut File, simulate_depth: usize) {
-    let mut board = Board::new();
-
-    let cpu = CPU::from_log_file(winner_log_file);
-    let cpu = if let Err(e) = cpu {
+/// TD(λ)で学習する
+/// チェックポイントが無ければ指定したファイルの最後のCPU(無ければ`CPU::new_alpha`)から始める
+pub fn learn_td(initial_file_name: Option<String>, config: &Config, seed: u64) {
+    let td_log_path = Path::new(&config.td_latest_file_name);
+    let learner = TdLearner::from_log_file(td_log_path);
+    let mut learner = if let Err(e) = learner {
         eprintln!("{}", e);
-        CPU::new_alpha()
+        let initial = match initial_file_name {
+            Some(file_name) => CPU::from_log_file(&mut File::open(file_name).unwrap()).unwrap(),
+            None => CPU::new_alpha(),
+        };
+        TdLearner::new(&initial, seed)
     } else {
-        cpu.unwrap()
+        learner.unwrap()
     };
+    println!("Seed: {}", learner.seed);
 
-    loop {
-        eprintln!("{:?}score: {}\n", board, cpu.eval_board(&board));
+    let references = Reference::suite(
+        &config.benchmark_alpha_depths,
+        &config.benchmark_mcts_iterations,
+    );
 
-        let next = if board.player == Player::Black {
-            cpu.choose_best(&board, simulate_depth)
-        } else {
-            input()
-        };
+    // Ctrl-Cでは今の反復を終えてから保存して止める
+    install_stop_handler();
+    let stop_condition = StopCondition::new(
+        config.max_generation,
+        config.time_limit_secs.map(Duration::from_secs),
+    );
+    let mut logged = true;
 
-        match board.update(next) {
-            Err(e) => {
-                eprintln!("{}", e);
-                continue;
+    loop {
+        // 次に行う反復の番号を世代数として扱う
+        if let Some(reason) = stop_condition.check(learner.iteration + 1) {
+            if !logged {
+                learner
+                    .log(td_log_path, config.checkpoint_keep_num)
+                    .unwrap();
             }
-            Ok(JudgeResult::Continue) => continue,
-            Ok(JudgeResult::Draw) => {
-                eprintln!("{:?}", board);
-                println!("Draw!");
-                break;
+            println!("{}: stopped after iteration {}", reason, learner.iteration);
+            break;
+        }
+
+        let start = Instant::now();
+        let summary = learner.learn_iteration(config);
+        println!(
+            "Iteration {}: {} games, {} positions, mse {:.6}, {:.1}s",
+            learner.iteration,
+            summary.games,
+            summary.positions,
+            summary.mean_squared_error,
+            start.elapsed().as_secs_f64()
+        );
+
+        if config.metrics_benchmark_interval > 0
+            && learner.iteration % config.metrics_benchmark_interval == 0
+        {
+            let mut rng = StdRng::seed_from_u64(learner.seed ^ learner.iteration as u64);
+            let openings = make_openings(
+                config.benchmark_opening_num,
+                config.opening_random_plies,
+                config.use_opening_book,
+                &mut rng,
+            );
+            let results = benchmark(
+                &learner.cpu(),
+                config.benchmark_depth,
+                &references,
+                &openings,
+                &mut rng,
+            );
+            for result in results {
+                println!("  vs {}: {:.1}%", result.name, result.win_rate() * 100.0);
             }
-            Ok(JudgeResult::Win(winner)) => {
-                eprintln!("{:?}", board);
-                println!("{:?} wins!", winner);
-                break;
+        }
+
+        logged = learner.iteration % config.td_log_interval.max(1) == 0;
+        if logged {
+            learner
+                .log(td_log_path, config.checkpoint_keep_num)
+                .unwrap();
+        }
+    }
+}
+
+/// トーナメントを保存し、その中で最も強いCPUを`winner_latest_file_name`と同じ形式で書き足す
+fn log_learning(tournament: &Tournament, tournament_log_path: &Path, config: &Config) {
+    tournament
+        .log(
+            tournament_log_path,
+            config.checkpoint_keep_num,
+            config.checkpoint_format,
+        )
+        .unwrap();
+    let mut iter = tournament.cpus.iter();
+    let first = iter.next().unwrap();
+    let winner = iter.fold(first, |a, b| eval_cpu(a, b, config.learning_depth));
+
+    let winner_path = Path::new(&config.winner_latest_file_name);
+    let mut archive = Archive::from_log_file(&mut File::open(winner_path).unwrap()).unwrap();
+    archive
+        .push(
+            ArchiveEntry::new(winner.clone(), tournament.generation),
+            winner_path,
+        )
+        .unwrap();
+}
+
+/// 保存済みのCPUと基準のCPUで総当たり戦を行い、レーティングの表を更新して表示する
+pub fn rate(cpu_file_names: &[String], config: &Config, rng: &mut impl Rng) {
+    let rating_table_path = Path::new(&config.rating_table_file_name);
+    let table = RatingTable::from_log_file(rating_table_path);
+    let mut table = if let Err(e) = table {
+        eprintln!("{}", e);
+        RatingTable::new()
+    } else {
+        table.unwrap()
+    };
+
+    let mut players = RatedPlayer::references(
+        &config.rating_reference_depths,
+        &config.rating_mcts_iterations,
+    );
+    for file_name in cpu_file_names {
+        let cpu = CPU::from_log_file(&mut File::open(file_name).unwrap()).unwrap();
+        players.push(RatedPlayer::Cpu {
+            name: file_name.clone(),
+            cpu,
+            depth: config.rating_depth,
+        });
+    }
+
+    let openings = make_openings(
+        config.rating_opening_num,
+        config.opening_random_plies,
+        config.use_opening_book,
+        rng,
+    );
+    run_rating(&players, &mut table, &openings, rng);
+    table.log(rating_table_path).unwrap();
+
+    for rating in table.sorted() {
+        let (low, high) = rating.interval();
+        println!(
+            "{}: {:.0} ({:.0} - {:.0}), {} games",
+            rating.name, rating.rating, low, high, rating.games
+        );
+    }
+}
+
+/// ファイルの最後のCPUを基準の対戦相手と対局させ、勝率を表示する
+pub fn run_benchmark(cpu_file: &mut File, config: &Config, rng: &mut impl Rng) {
+    let cpu = CPU::from_log_file(cpu_file).unwrap();
+    let openings = make_openings(
+        config.benchmark_opening_num,
+        config.opening_random_plies,
+        config.use_opening_book,
+        rng,
+    );
+    let references = Reference::suite(
+        &config.benchmark_alpha_depths,
+        &config.benchmark_mcts_iterations,
+    );
+
+    for result in benchmark(&cpu, config.benchmark_depth, &references, &openings, rng) {
+        println!(
+            "vs {}: {:.1}% (win: {}, draw: {}, lose: {})",
+            result.name,
+            result.win_rate() * 100.0,
+            result.result.win,
+            result.result.draw,
+            result.result.lose
+        );
+    }
+}
+
+/// 優勝CPUの記録の一覧(list)、2体の対局(compare)、過去のCPUへの巻き戻し(rollback)を行う
+/// CPUは"3"のような番号か"g25"のような世代数で指定する
+pub fn archive(winner_log_file: &mut File, args: &[String], config: &Config, rng: &mut impl Rng) {
+    let exit = |e: String| -> ! {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    };
+    let usage = || -> ! { exit(ARCHIVE_USAGE.to_string()) };
+
+    let mut archive =
+        Archive::from_log_file(winner_log_file).unwrap_or_else(|e| exit(e.to_string()));
+    let select = |archive: &Archive, index: usize| {
+        let selector = args.get(index).unwrap_or_else(|| usage());
+        archive
+            .select(selector)
+            .cloned()
+            .unwrap_or_else(|| exit(format!("No such CPU: {}", selector)))
+    };
+
+    match args.first().map(|arg| arg.as_str()) {
+        None | Some("list") => {
+            for (index, entry) in archive.entries.iter().enumerate() {
+                let generation = entry.generation.map_or("-".to_string(), |g| g.to_string());
+                let timestamp = entry.timestamp.map_or("-".to_string(), |t| t.to_string());
+                println!(
+                    "{}: generation {}, timestamp {}",
+                    index, generation, timestamp
+                );
             }
         }
+        Some("compare") => {
+            let (left, right) = (select(&archive, 1), select(&archive, 2));
+            let openings = make_openings(
+                config.benchmark_opening_num,
+                config.opening_random_plies,
+                config.use_opening_book,
+                rng,
+            );
+            let result = eval_match(&left.cpu, &right.cpu, config.benchmark_depth, &openings);
+            println!(
+                "{} vs {}: win: {}, draw: {}, lose: {}, disc diff: {}",
+                args[1], args[2], result.win, result.draw, result.lose, result.disc_diff
+            );
+        }
+        Some("rollback") => {
+            // 選んだCPUを最新として書き足す
+            let entry = select(&archive, 1);
+            archive
+                .push(entry, Path::new(&config.winner_latest_file_name))
+                .unwrap_or_else(|e| exit(e.to_string()));
+            println!("Rolled back to {}", args[1]);
+        }
+        _ => usage(),
     }
 }
 
-pub fn input() -> Choice {
-    let mut buf = String::new();
-    stdin().read_line(&mut buf).unwrap();
-    let buf = buf.trim();
+/// 指定したCPU(無ければ最新の優勝CPU)同士で自己対局し、局面を`selfplay_file_name`に追記する
+pub fn run_selfplay(
+    winner_latest_log_file: &mut File,
+    file_names: &[String],
+    config: &Config,
+    rng: &mut impl Rng,
+) {
+    let cpus = if file_names.is_empty() {
+        vec![CPU::from_log_file(winner_latest_log_file).unwrap()]
+    } else {
+        file_names
+            .iter()
+            .map(|file_name| CPU::from_log_file(&mut File::open(file_name).unwrap()).unwrap())
+            .collect()
+    };
+
+    let positions = selfplay(&cpus, config, rng);
+
+    let mut selfplay_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&config.selfplay_file_name)
+        .unwrap();
+    for position in &positions {
+        position.log(&mut selfplay_file).unwrap();
+    }
+    println!(
+        "Wrote {} positions to {}",
+        positions.len(),
+        config.selfplay_file_name
+    );
+}
+
+/// データセットに合わせて`CPU::new_alpha`から重みを調整し、`tuned_file_name`に追記する
+pub fn tune(dataset_file_name: &str, config: &Config) {
+    let positions = load_dataset(&mut File::open(dataset_file_name).unwrap()).unwrap();
+    println!("Positions: {}", positions.len());
+
+    let tuner = Tuner::new(
+        &positions,
+        config.tune_target,
+        config.tune_scale,
+        config.tune_learning_rate,
+    );
+    let initial = CPU::new_alpha();
+    let report_interval = (config.tune_epochs / 10).max(1);
+    let tuned = tuner.tune(&initial, config.tune_epochs, |epoch, loss| {
+        if epoch % report_interval == 1 || report_interval == 1 {
+            println!("Epoch {}: loss {:.6}", epoch, loss);
+        }
+    });
+    println!("Tuned loss: {:.6}", tuner.loss(&tuned));
+
+    let mut tuned_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&config.tuned_file_name)
+        .unwrap();
+    tuned.log(&mut tuned_file).unwrap();
+}
+
+/// JSON形式とバイナリ形式を相互に変換する
+pub fn convert_file(input: &str, output: &str) {
+    let bytes = std::fs::read(input).unwrap();
+    let converted = convert(&bytes).unwrap();
+    write_atomic(Path::new(output), &converted).unwrap();
+}
+
+/// `simulate`のオプション
+/// `--black`と`--white`には`human`、`cpu`(最新の優勝CPU)、`cpu:<ファイル>`、`mcts`、`random`、
+/// `engine:<コマンド>`を指定する(既定ではCPUが黒、人間が白)
+/// `--level`でCPUの難易度を、`--position`で`Board::to_position`の形式か"f5d6..."の棋譜で開始局面を指定する
+struct SimulateOptions {
+    black: String,
+    white: String,
+    level: Option<String>,
+    position: Option<String>,
+}
+
+fn parse_simulate_options(args: &[String]) -> Result<SimulateOptions, String> {
+    let mut options = SimulateOptions {
+        black: "cpu".to_string(),
+        white: "human".to_string(),
+        level: None,
+        position: None,
+    };
+
+    let mut iter = args.iter();
+    while let Some(key) = iter.next() {
+        let value = iter
+            .next()
+            .ok_or_else(|| format!("No value for {}", key))?
+            .clone();
+        match key.as_str() {
+            "--black" => options.black = value,
+            "--white" => options.white = value,
+            "--level" => options.level = Some(value),
+            "--position" => options.position = Some(value),
+            _ => return Err(format!("Unknown option: {}", key)),
+        }
+    }
+
+    Ok(options)
+}
 
-    match buf {
-        "s" => Choice::Skip,
+/// 64マス分の文字列で始まれば`Board::from_position`で、それ以外は棋譜として読む
+fn parse_start_board(position: &str) -> Result<Board, Error> {
+    let is_squares = position
+        .split_whitespace()
+        .next()
+        .is_some_and(|squares| squares.chars().count() == 64);
+    let board = if is_squares {
+        Board::from_position(position)?
+    } else {
+        board_from_moves(position)?
+    };
+
+    if board.make_legal_board() == 0 && !board.is_skip() {
+        return Err(Error::GameOver);
+    }
+
+    Ok(board)
+}
+
+/// `SimulateOptions`の対局者の指定から対局者を作る
+fn make_agent(
+    spec: &str,
+    winner: &CPU,
+    difficulty: &Option<Difficulty>,
+    config: &Config,
+    seed: u64,
+) -> Result<Box<dyn Agent>, String> {
+    let cpu_agent = |cpu: CPU| -> Box<dyn Agent> {
+        match difficulty {
+            Some(difficulty) => Box::new(CpuAgent::with_difficulty(cpu, difficulty, seed)),
+            None => Box::new(CpuAgent::with_noise(
+                cpu,
+                config.simulation_depth,
+                config.simulation_noise,
+                seed,
+            )),
+        }
+    };
+
+    match spec {
+        "human" => Ok(Box::new(HumanAgent {
+            evaluator: winner.clone(),
+            depth: config.simulation_depth,
+        })),
+        "cpu" => Ok(cpu_agent(winner.clone())),
+        "mcts" => Ok(Box::new(Mcts::new(
+            Mcts::DEFAULT_EXPLORATION,
+            Playout::Random,
+            Budget::Iterations(config.simulation_mcts_iterations),
+            seed,
+        ))),
+        "random" => Ok(Box::new(ReferenceAgent::random(seed))),
         _ => {
-            if buf.chars().count() != 2 {
-                eprintln!("Wrong input length");
-                return input();
+            if let Some(file_name) = spec.strip_prefix("cpu:") {
+                let cpu = File::open(file_name)
+                    .map_err(Error::from)
+                    .and_then(|mut file| CPU::from_log_file(&mut file))
+                    .map_err(|e| format!("{}: {}", file_name, e))?;
+                Ok(cpu_agent(cpu))
+            } else if let Some(command) = spec.strip_prefix("engine:") {
+                let engine = ExternalAgent::spawn(command).map_err(|e| e.to_string())?;
+                Ok(Box::new(engine))
+            } else {
+                Err(format!("Unknown player: {}", spec))
             }
+        }
+    }
+}
 
-            if buf.chars().map(|c| c.to_digit(10)).any(|o| o.is_none()) {
-                eprintln!("Wrong input");
-                return input();
-            }
+/// オプションで指定した対局者同士で対局し、終局後に盤面と棋譜と結果を表示する
+/// 人間とCPUの対局で難易度を指定しなければ、対局の開始時に選ばせる
+pub fn simulate(winner_log_file: &mut File, args: &[String], config: &Config, seed: u64) {
+    let exit = |e: String| -> ! {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    };
+
+    let options = parse_simulate_options(args).unwrap_or_else(|e| exit(e));
+    let board = match &options.position {
+        Some(position) => parse_start_board(position).unwrap_or_else(|e| exit(e.to_string())),
+        None => Board::new(),
+    };
+
+    let cpu = CPU::from_log_file(winner_log_file);
+    let cpu = if let Err(e) = cpu {
+        eprintln!("{}", e);
+        CPU::new_alpha()
+    } else {
+        cpu.unwrap()
+    };
+
+    let players = [&options.black, &options.white];
+    let difficulty = match &options.level {
+        Some(level) => match Difficulty::find(&config.difficulties, level) {
+            Some(difficulty) => Some(difficulty.clone()),
+            None => exit(format!("Unknown difficulty: {}", level)),
+        },
+        None if players.iter().any(|player| player.as_str() == "human")
+            && players.iter().any(|player| player.starts_with("cpu")) =>
+        {
+            select_difficulty(config)
+        }
+        None => None,
+    };
+
+    let mut black =
+        make_agent(&options.black, &cpu, &difficulty, config, seed).unwrap_or_else(|e| exit(e));
+    let mut white = make_agent(
+        &options.white,
+        &cpu,
+        &difficulty,
+        config,
+        seed.wrapping_add(1),
+    )
+    .unwrap_or_else(|e| exit(e));
+
+    let record = play_agents(black.as_mut(), white.as_mut(), &board);
+    eprintln!("{:?}", record.board);
+    println!("{}", record.moves());
+    match record.result {
+        JudgeResult::Win(winner) => println!("{:?} wins!", winner),
+        JudgeResult::Draw => println!("Draw!"),
+        JudgeResult::Continue => println!("Quit"),
+    }
+}
+
+/// 難易度を番号か名前で選ばせる
+/// 空行なら`None`を返し、`simulation_depth`と`simulation_noise`を使う
+fn select_difficulty(config: &Config) -> Option<Difficulty> {
+    for (i, difficulty) in config.difficulties.iter().enumerate() {
+        eprintln!("{}: {}", i + 1, difficulty.name);
+    }
+    eprintln!(
+        "Select difficulty (empty for depth {}):",
+        config.simulation_depth
+    );
+
+    loop {
+        let mut buf = String::new();
+        if stdin().read_line(&mut buf).unwrap() == 0 || buf.trim().is_empty() {
+            return None;
+        }
 
-            let v = buf
-                .chars()
-                .map(|c| c.to_digit(10).unwrap() as usize)
-                .collect::<Vec<_>>();
-            Choice::Coordinate((v[0], v[1]))
+        match Difficulty::find(&config.difficulties, &buf) {
+            Some(difficulty) => return Some(difficulty.clone()),
+            None => eprintln!("Unknown difficulty: {}", buf.trim()),
         }
     }
 }
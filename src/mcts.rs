@@ -0,0 +1,227 @@
+use crate::board::{Board, Choice, JudgeResult, Player};
+use crate::cpu::CPU;
+use rand::prelude::{SliceRandom, StdRng};
+use rand::{Rng, SeedableRng};
+use std::time::{Duration, Instant};
+
+/// プレイアウトでの手の選び方
+#[derive(Clone, Debug)]
+pub enum Playout {
+    /// 合法手からランダムに選ぶ
+    Random,
+    /// 1手読みの`choose_best`で選ぶ(`EVALUATION_RANDOM_PROB`の確率でランダムに選ぶ)
+    Evaluation(CPU),
+}
+
+/// 1手あたりの探索の予算
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Budget {
+    /// 反復の回数
+    Iterations(usize),
+    /// 時間
+    Time(Duration),
+}
+
+/// 評価値によるプレイアウトでランダムな手を選ぶ確率
+const EVALUATION_RANDOM_PROB: f64 = 0.1;
+
+/// 探索木の節点
+struct Node {
+    board: Board,
+    /// 親の盤面からこの盤面にする手
+    choice: Choice,
+    /// この盤面にした手で終局していればその結果
+    judge: JudgeResult,
+    children: Vec<Node>,
+    /// まだ子を作っていない手
+    untried: Vec<Choice>,
+    visits: usize,
+    /// 親の盤面で次の手の人から見た報酬(勝ち1.0、引き分け0.5)の合計
+    value: f64,
+}
+
+impl Node {
+    fn new(board: Board, choice: Choice, judge: JudgeResult) -> Self {
+        let untried = match judge {
+            JudgeResult::Continue if board.is_skip() => vec![Choice::Skip],
+            JudgeResult::Continue => board.legal_choices(),
+            _ => Vec::new(),
+        };
+
+        Self {
+            board,
+            choice,
+            judge,
+            children: Vec::new(),
+            untried,
+            visits: 0,
+            value: 0.0,
+        }
+    }
+
+    /// 自分か`depth`手先までの子孫から盤面が一致するものを取り出す
+    fn take_subtree(self, board: &Board, depth: usize) -> Option<Node> {
+        if &self.board == board {
+            return Some(self);
+        }
+
+        if depth == 0 {
+            return None;
+        }
+
+        self.children
+            .into_iter()
+            .find_map(|child| child.take_subtree(board, depth - 1))
+    }
+}
+
+/// 黒から見た報酬
+fn black_reward(judge: JudgeResult) -> f64 {
+    match judge {
+        JudgeResult::Win(Player::Black) => 1.0,
+        JudgeResult::Win(Player::White) => 0.0,
+        _ => 0.5,
+    }
+}
+
+/// 黒から見た報酬を`player`から見た報酬にする
+fn reward_for(black_reward: f64, player: Player) -> f64 {
+    match player {
+        Player::Black => black_reward,
+        Player::White => 1.0 - black_reward,
+    }
+}
+
+/// UCTによるモンテカルロ木探索のプレイヤー
+/// 選んだ手の部分木を残し、次の手番で再利用する
+pub struct Mcts {
+    /// UCB1の探索項の係数
+    pub exploration: f64,
+    pub playout: Playout,
+    pub budget: Budget,
+    rng: StdRng,
+    root: Option<Node>,
+}
+
+impl Mcts {
+    /// UCB1の探索項の係数の標準的な値
+    pub const DEFAULT_EXPLORATION: f64 = std::f64::consts::SQRT_2;
+
+    pub fn new(exploration: f64, playout: Playout, budget: Budget, seed: u64) -> Self {
+        Self {
+            exploration,
+            playout,
+            budget,
+            rng: StdRng::seed_from_u64(seed),
+            root: None,
+        }
+    }
+
+    /// 再利用する探索木を捨てる
+    pub fn reset(&mut self) {
+        self.root = None;
+    }
+
+    /// 盤面から終局までプレイアウトし、黒から見た報酬を返す
+    fn playout(&mut self, board: &Board) -> f64 {
+        let mut board = board.clone();
+
+        loop {
+            let choice = if board.is_skip() {
+                Choice::Skip
+            } else {
+                match &self.playout {
+                    Playout::Evaluation(cpu) if EVALUATION_RANDOM_PROB <= self.rng.gen::<f64>() => {
+                        cpu.choose_best(&board, 1)
+                    }
+                    _ => *board.legal_choices().choose(&mut self.rng).unwrap(),
+                }
+            };
+
+            match board.update(choice).unwrap() {
+                JudgeResult::Continue => continue,
+                judge => return black_reward(judge),
+            }
+        }
+    }
+
+    /// 選択、展開、プレイアウト、逆伝播を1回行い、黒から見た報酬を返す
+    fn iterate(&mut self, node: &mut Node) -> f64 {
+        let reward = if node.judge != JudgeResult::Continue {
+            black_reward(node.judge)
+        } else if let Some(choice) = node.untried.pop() {
+            let mut board = node.board.clone();
+            let judge = board.update(choice).unwrap();
+            let mut child = Node::new(board, choice, judge);
+
+            let reward = match judge {
+                JudgeResult::Continue => self.playout(&child.board),
+                judge => black_reward(judge),
+            };
+            child.visits += 1;
+            child.value += reward_for(reward, node.board.player);
+            node.children.push(child);
+            reward
+        } else {
+            let log_visits = (node.visits.max(1) as f64).ln();
+            let exploration = self.exploration;
+            let child = node
+                .children
+                .iter_mut()
+                .max_by(|a, b| {
+                    let ucb = |child: &Node| {
+                        child.value / child.visits as f64
+                            + exploration * (log_visits / child.visits as f64).sqrt()
+                    };
+                    ucb(a).partial_cmp(&ucb(b)).unwrap()
+                })
+                .unwrap();
+
+            let reward = self.iterate(child);
+            child.visits += 1;
+            child.value += reward_for(reward, node.board.player);
+            reward
+        };
+
+        node.visits += 1;
+        reward
+    }
+
+    /// 予算の分だけ探索し、最も多く訪れた手を選ぶ
+    pub fn choose(&mut self, board: &Board) -> Choice {
+        if board.is_skip() {
+            return Choice::Skip;
+        }
+
+        // 前回選んだ手の部分木から、相手が打った後の盤面を探す
+        let mut root = self
+            .root
+            .take()
+            .and_then(|root| root.take_subtree(board, 2))
+            .unwrap_or_else(|| Node::new(board.clone(), Choice::Skip, JudgeResult::Continue));
+
+        let start = Instant::now();
+        let mut iterations = 0;
+        loop {
+            let done = match self.budget {
+                Budget::Iterations(num) => iterations >= num.max(1),
+                Budget::Time(time) => iterations > 0 && start.elapsed() >= time,
+            };
+            if done {
+                break;
+            }
+
+            self.iterate(&mut root);
+            iterations += 1;
+        }
+
+        let best = root
+            .children
+            .into_iter()
+            .max_by_key(|child| child.visits)
+            .unwrap();
+        let choice = best.choice;
+        self.root = Some(best);
+        choice
+    }
+}
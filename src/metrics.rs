@@ -0,0 +1,93 @@
+use crate::cpu::WEIGHT_LEN;
+use crate::error::Result;
+use crate::tournament::GenerationSummary;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::Duration;
+
+/// 1世代分の学習の指標
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GenerationMetrics {
+    pub generation: usize,
+    /// 勝敗がついた対局の数
+    pub decided: usize,
+    /// 引き分けの対局の数
+    pub draw: usize,
+    /// 段階ごと、遺伝子ごとの重みの平均
+    pub weight_means: [[f64; WEIGHT_LEN]; 4],
+    /// 段階ごと、遺伝子ごとの重みの分散
+    pub weight_variances: [[f64; WEIGHT_LEN]; 4],
+    /// 全CPUの重みの重心からの距離の平均
+    pub diversity: f64,
+    /// その世代の優勝CPUの基準の対戦相手に対する勝率
+    pub reference_win_rate: Option<f64>,
+    /// 世代の更新にかかった秒数
+    pub wall_time: f64,
+}
+
+impl GenerationMetrics {
+    /// `generation`世代の更新結果から、その世代で対局したCPUの指標を集計する
+    pub fn new(
+        generation: usize,
+        summary: &GenerationSummary,
+        wall_time: Duration,
+        reference_win_rate: Option<f64>,
+    ) -> Self {
+        let len = summary.population.len() as f64;
+        let mut weight_means = [[0.0; WEIGHT_LEN]; 4];
+        let mut weight_variances = [[0.0; WEIGHT_LEN]; 4];
+
+        for cpu in &summary.population {
+            for (stage, weights) in cpu.stages().iter().enumerate() {
+                for (k, &weight) in weights.iter().enumerate() {
+                    weight_means[stage][k] += weight as f64 / len;
+                }
+            }
+        }
+
+        let mut diversity = 0.0;
+        for cpu in &summary.population {
+            let mut distance = 0.0;
+
+            for (stage, weights) in cpu.stages().iter().enumerate() {
+                for (k, &weight) in weights.iter().enumerate() {
+                    let diff = weight as f64 - weight_means[stage][k];
+                    weight_variances[stage][k] += diff * diff / len;
+                    distance += diff * diff;
+                }
+            }
+
+            diversity += distance.sqrt() / len;
+        }
+
+        Self {
+            generation,
+            decided: summary.decided,
+            draw: summary.draw,
+            weight_means,
+            weight_variances,
+            diversity,
+            reference_win_rate,
+            wall_time: wall_time.as_secs_f64(),
+        }
+    }
+
+    /// JSON Lines形式で1行追記する
+    pub fn log(&self, log_file: &mut File) -> Result<()> {
+        let str = serde_json::to_string(self)?;
+        log_file.write_all(format!("{}\n", str).as_bytes())?;
+        Ok(log_file.flush()?)
+    }
+
+    /// ファイルに記録済みの最後の世代(読めない行は無視する)
+    pub fn last_logged_generation(path: &Path) -> Option<usize> {
+        let file = File::open(path).ok()?;
+        BufReader::new(file)
+            .lines()
+            .map_while(|line| line.ok())
+            .filter_map(|line| serde_json::from_str::<Self>(&line).ok())
+            .map(|metrics| metrics.generation)
+            .max()
+    }
+}
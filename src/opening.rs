@@ -0,0 +1,87 @@
+use crate::board::{coordinate_from_notation, Board, Choice, JudgeResult};
+use crate::error::{Error, Result};
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+/// よく知られた定石の序盤の手順
+pub const OPENING_BOOK: [&str; 8] = [
+    "f5d6c3d3c4",       // 虎
+    "f5d6c5f4e3f6",     // 牛からの進行
+    "f5f6e6f4c3",       // 野兎
+    "f5f6e6f4g5",       // ヒース
+    "f5d6c5",           // 牛
+    "f5f4",             // 並び取り
+    "f5d6",             // 縦取り
+    "f5f6",             // 斜め取り
+];
+
+/// 棋譜表記の手順を初期盤面から打った盤面を返す
+pub fn board_from_moves(moves: &str) -> Result<Board> {
+    let mut board = Board::new();
+    let chars = moves.chars().collect::<Vec<_>>();
+
+    for notation in chars.chunks(2) {
+        let notation = notation.iter().collect::<String>();
+        let co = coordinate_from_notation(&notation)
+            .ok_or_else(|| Error::Parse(format!("Invalid notation: {}", notation)))?;
+
+        if board.update(Choice::Coordinate(co))? != JudgeResult::Continue {
+            return Err(Error::GameOver);
+        }
+    }
+
+    Ok(board)
+}
+
+/// 盤面からランダムに`plies`手進める
+/// 途中で終局してしまう場合はその直前で止める
+pub fn randomize(board: &Board, plies: usize, rng: &mut impl Rng) -> Board {
+    let mut board = board.clone();
+
+    for _ in 0..plies {
+        let choice = if board.is_skip() {
+            Choice::Skip
+        } else {
+            match board.legal_choices().choose(rng) {
+                Some(&choice) => choice,
+                None => break,
+            }
+        };
+
+        let mut next = board.clone();
+        match next.update(choice) {
+            Ok(JudgeResult::Continue) => board = next,
+            _ => break,
+        }
+    }
+
+    board
+}
+
+/// 適応度評価に使う開始局面を`num`個作る
+/// `use_book`なら定石を順に使い、それぞれ`random_plies`手ランダムに進める
+pub fn make_openings(
+    num: usize,
+    random_plies: usize,
+    use_book: bool,
+    rng: &mut impl Rng,
+) -> Vec<Board> {
+    if num == 0 {
+        return vec![Board::new()];
+    }
+
+    let mut book = OPENING_BOOK.to_vec();
+    book.shuffle(rng);
+
+    (0..num)
+        .map(|i| {
+            let board = if use_book {
+                board_from_moves(book[i % book.len()]).unwrap()
+            } else {
+                Board::new()
+            };
+
+            randomize(&board, random_plies, rng)
+        })
+        .collect()
+}
@@ -0,0 +1,325 @@
+use crate::board::{Board, Choice};
+use crate::checkpoint::write_atomic;
+use crate::cpu::{eval_match_by, CPU};
+use crate::error::Result;
+use crate::reference::{Reference, ReferencePlayer};
+use rand::Rng;
+use std::f64::consts::PI;
+use std::path::Path;
+
+/// Glicko-2の内部スケールへの変換係数
+const SCALE: f64 = 173.7178;
+/// 変動率の変化の大きさを制限する定数
+const TAU: f64 = 0.5;
+const EPSILON: f64 = 0.000_001;
+
+/// Glicko-2によるレーティング
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Rating {
+    pub name: String,
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+    pub games: usize,
+}
+
+impl Rating {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            rating: 1500.0,
+            deviation: 350.0,
+            volatility: 0.06,
+            games: 0,
+        }
+    }
+
+    /// 95%信頼区間
+    pub fn interval(&self) -> (f64, f64) {
+        (
+            self.rating - 1.96 * self.deviation,
+            self.rating + 1.96 * self.deviation,
+        )
+    }
+
+    fn mu(&self) -> f64 {
+        (self.rating - 1500.0) / SCALE
+    }
+
+    fn phi(&self) -> f64 {
+        self.deviation / SCALE
+    }
+
+    /// 1期間分の(相手, 得点)の結果からレーティングを更新したものを返す
+    fn updated(&self, results: &[(&Rating, f64)]) -> Self {
+        let (mu, phi, sigma) = (self.mu(), self.phi(), self.volatility);
+
+        if results.is_empty() {
+            return Self {
+                deviation: (phi * phi + sigma * sigma).sqrt() * SCALE,
+                ..self.clone()
+            };
+        }
+
+        fn g(phi: f64) -> f64 {
+            1.0 / (1.0 + 3.0 * phi * phi / (PI * PI)).sqrt()
+        }
+
+        let mut v_inv = 0.0;
+        let mut sum = 0.0;
+        for (opponent, score) in results {
+            let g = g(opponent.phi());
+            let e = 1.0 / (1.0 + (-g * (mu - opponent.mu())).exp());
+            v_inv += g * g * e * (1.0 - e);
+            sum += g * (score - e);
+        }
+        let v = 1.0 / v_inv;
+        let delta = v * sum;
+
+        // Illinois法で新しい変動率を求める
+        let a = (sigma * sigma).ln();
+        let f = |x: f64| {
+            let ex = x.exp();
+            ex * (delta * delta - phi * phi - v - ex)
+                / (2.0 * (phi * phi + v + ex) * (phi * phi + v + ex))
+                - (x - a) / (TAU * TAU)
+        };
+
+        let mut big_a = a;
+        let mut big_b = if delta * delta > phi * phi + v {
+            (delta * delta - phi * phi - v).ln()
+        } else {
+            let mut k = 1.0;
+            while f(a - k * TAU) < 0.0 {
+                k += 1.0;
+            }
+            a - k * TAU
+        };
+
+        let mut f_a = f(big_a);
+        let mut f_b = f(big_b);
+        while (big_b - big_a).abs() > EPSILON {
+            let big_c = big_a + (big_a - big_b) * f_a / (f_b - f_a);
+            let f_c = f(big_c);
+
+            if f_c * f_b <= 0.0 {
+                big_a = big_b;
+                f_a = f_b;
+            } else {
+                f_a /= 2.0;
+            }
+
+            big_b = big_c;
+            f_b = f_c;
+        }
+
+        let sigma = (big_a / 2.0).exp();
+        let phi_star = (phi * phi + sigma * sigma).sqrt();
+        let phi = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / v).sqrt();
+        let mu = mu + phi * phi * sum;
+
+        Self {
+            name: self.name.clone(),
+            rating: mu * SCALE + 1500.0,
+            deviation: phi * SCALE,
+            volatility: sigma,
+            games: self.games + results.len(),
+        }
+    }
+}
+
+/// 名前ごとのレーティングの表
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RatingTable {
+    pub ratings: Vec<Rating>,
+}
+
+impl RatingTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn log(&self, path: &Path) -> Result<()> {
+        let str = serde_json::to_string(self)?;
+        Ok(write_atomic(path, str.as_bytes())?)
+    }
+
+    pub fn from_log_file(path: &Path) -> Result<Self> {
+        let buf = std::fs::read_to_string(path)?;
+        let table = serde_json::from_str(&buf)?;
+        Ok(table)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Rating> {
+        self.ratings.iter().find(|rating| rating.name == name)
+    }
+
+    fn index_or_insert(&mut self, name: &str) -> usize {
+        match self.ratings.iter().position(|rating| rating.name == name) {
+            Some(index) => index,
+            None => {
+                self.ratings.push(Rating::new(name));
+                self.ratings.len() - 1
+            }
+        }
+    }
+
+    /// 1期間分の対局結果(勝者から見て1.0、引き分け0.5)でまとめて更新する
+    /// 表にない名前は初期値で追加される
+    pub fn update(&mut self, games: &[(&str, &str, f64)]) {
+        let mut results = vec![Vec::new(); self.ratings.len()];
+        for &(left, right, score) in games {
+            let left = self.index_or_insert(left);
+            let right = self.index_or_insert(right);
+            results.resize(self.ratings.len(), Vec::new());
+
+            results[left].push((right, score));
+            results[right].push((left, 1.0 - score));
+        }
+
+        let ratings = self
+            .ratings
+            .iter()
+            .zip(&results)
+            .map(|(rating, results)| {
+                let results = results
+                    .iter()
+                    .map(|&(opponent, score)| (&self.ratings[opponent], score))
+                    .collect::<Vec<_>>();
+                rating.updated(&results)
+            })
+            .collect();
+
+        self.ratings = ratings;
+    }
+
+    /// レーティングの高い順に並べる
+    pub fn sorted(&self) -> Vec<&Rating> {
+        let mut ratings = self.ratings.iter().collect::<Vec<_>>();
+        ratings.sort_by(|a, b| b.rating.partial_cmp(&a.rating).unwrap());
+        ratings
+    }
+}
+
+/// レーティング対象の対局者
+#[derive(Clone, Debug)]
+pub enum RatedPlayer {
+    /// 保存済みのCPUを指定した深さで探索させる
+    Cpu {
+        name: String,
+        cpu: CPU,
+        depth: usize,
+    },
+    /// 基準の対戦相手
+    Reference(Reference),
+}
+
+impl RatedPlayer {
+    /// 基準となる`CPU::new_alpha`を各深さで、MCTSを各反復回数で作る
+    pub fn references(alpha_depths: &[usize], mcts_iterations: &[usize]) -> Vec<Self> {
+        let alpha = alpha_depths.iter().map(|&depth| Reference::Alpha(depth));
+        let mcts = mcts_iterations.iter().map(|&num| Reference::Mcts(num));
+        alpha.chain(mcts).map(RatedPlayer::Reference).collect()
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            RatedPlayer::Cpu { name, .. } => name.clone(),
+            RatedPlayer::Reference(reference) => reference.name(),
+        }
+    }
+
+    /// 1組の対局で手を選ぶ関数(MCTSは探索木を再利用する)
+    fn chooser(&self, seed: u64) -> Box<dyn FnMut(&Board) -> Choice + '_> {
+        match self {
+            RatedPlayer::Cpu { cpu, depth, .. } => {
+                Box::new(move |board| cpu.choose_best(board, *depth))
+            }
+            RatedPlayer::Reference(reference) => {
+                let mut player = ReferencePlayer::new(*reference, seed);
+                Box::new(move |board| player.choose(board))
+            }
+        }
+    }
+}
+
+/// 全ての組み合わせで対局し、その結果を1期間としてレーティングを更新する
+pub fn run_rating(
+    players: &[RatedPlayer],
+    table: &mut RatingTable,
+    openings: &[Board],
+    rng: &mut impl Rng,
+) {
+    let names = players.iter().map(RatedPlayer::name).collect::<Vec<_>>();
+    let mut games = Vec::new();
+
+    for i in 0..players.len() {
+        for j in 0..i {
+            let (left, right) = (names[i].as_str(), names[j].as_str());
+            let result = eval_match_by(
+                players[i].chooser(rng.gen()),
+                players[j].chooser(rng.gen()),
+                openings,
+            );
+
+            for _ in 0..result.win {
+                games.push((left, right, 1.0));
+            }
+            for _ in 0..result.draw {
+                games.push((left, right, 0.5));
+            }
+            for _ in 0..result.lose {
+                games.push((left, right, 0.0));
+            }
+        }
+    }
+
+    table.update(&games);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rating(rating: f64, deviation: f64) -> Rating {
+        Rating {
+            rating,
+            deviation,
+            ..Rating::new("")
+        }
+    }
+
+    /// Glickmanの"Example of the Glicko-2 system"の計算例
+    #[test]
+    fn updated_matches_glickman_example() {
+        let player = rating(1500.0, 200.0);
+        let opponents = [
+            rating(1400.0, 30.0),
+            rating(1550.0, 100.0),
+            rating(1700.0, 300.0),
+        ];
+        let results = [
+            (&opponents[0], 1.0),
+            (&opponents[1], 0.0),
+            (&opponents[2], 0.0),
+        ];
+
+        let updated = player.updated(&results);
+        assert!(
+            (updated.rating - 1464.06).abs() < 0.01,
+            "{}",
+            updated.rating
+        );
+        assert!(
+            (updated.deviation - 151.52).abs() < 0.01,
+            "{}",
+            updated.deviation
+        );
+        assert!(
+            (updated.volatility - 0.05999).abs() < 0.00001,
+            "{}",
+            updated.volatility
+        );
+        assert_eq!(updated.games, 3);
+    }
+}
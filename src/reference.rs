@@ -0,0 +1,163 @@
+use crate::board::{Board, Choice};
+use crate::cpu::{eval_match_by, MatchResult, CPU};
+use crate::mcts::{Budget, Mcts, Playout};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+/// 学習したCPUの強さを測るための固定の対戦相手
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Reference {
+    /// 合法手からランダムに選ぶ
+    Random,
+    /// 最も多く石を返す手を選ぶ
+    Greedy,
+    /// 相手の合法手が最も少なくなる手を選ぶ
+    MobilityMinimizer,
+    /// 指定した深さで探索する`CPU::new_alpha`
+    Alpha(usize),
+    /// 1手あたり指定した回数反復する、ランダムなプレイアウトのMCTS
+    Mcts(usize),
+}
+
+impl Reference {
+    /// 基準となる対戦相手一式
+    pub fn suite(alpha_depths: &[usize], mcts_iterations: &[usize]) -> Vec<Self> {
+        let mut suite = vec![
+            Reference::Random,
+            Reference::Greedy,
+            Reference::MobilityMinimizer,
+        ];
+        suite.extend(alpha_depths.iter().map(|&depth| Reference::Alpha(depth)));
+        suite.extend(mcts_iterations.iter().map(|&num| Reference::Mcts(num)));
+        suite
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Reference::Random => "random".to_string(),
+            Reference::Greedy => "greedy".to_string(),
+            Reference::MobilityMinimizer => "mobility".to_string(),
+            Reference::Alpha(depth) => format!("alpha-d{}", depth),
+            Reference::Mcts(num) => format!("mcts-{}", num),
+        }
+    }
+
+    /// 次の一手を選ぶ
+    /// MCTSは毎回新しい探索木で探索する(探索木を再利用するには`ReferencePlayer`を使う)
+    pub fn choose(&self, board: &Board, rng: &mut impl Rng) -> Choice {
+        if board.is_skip() {
+            return Choice::Skip;
+        }
+
+        let choices = board.legal_choices();
+
+        // 打った後の盤面の評価が最小になる手を選ぶ
+        let min_by = |key: &dyn Fn(&Board) -> isize| {
+            *choices
+                .iter()
+                .min_by_key(|&&choice| {
+                    let mut board = board.clone();
+                    board.update(choice).unwrap();
+                    key(&board)
+                })
+                .unwrap()
+        };
+
+        match self {
+            Reference::Random => *choices.choose(rng).unwrap(),
+            Reference::Greedy => min_by(&|next| -(next.opponent_board.count_ones() as isize)),
+            Reference::MobilityMinimizer => {
+                min_by(&|next| next.make_legal_board().count_ones() as isize)
+            }
+            Reference::Alpha(depth) => CPU::new_alpha().choose_best(board, *depth),
+            Reference::Mcts(num) => Self::new_mcts(*num, rng.gen()).choose(board),
+        }
+    }
+
+    fn new_mcts(num: usize, seed: u64) -> Mcts {
+        Mcts::new(
+            Mcts::DEFAULT_EXPLORATION,
+            Playout::Random,
+            Budget::Iterations(num),
+            seed,
+        )
+    }
+}
+
+/// 対局中の基準の対戦相手
+/// MCTSは1つの探索木を使い続け、選んだ手の部分木を次の手番で再利用する
+pub struct ReferencePlayer {
+    pub reference: Reference,
+    rng: StdRng,
+    mcts: Option<Mcts>,
+}
+
+impl ReferencePlayer {
+    pub fn new(reference: Reference, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mcts = match reference {
+            Reference::Mcts(num) => Some(Reference::new_mcts(num, rng.gen())),
+            _ => None,
+        };
+
+        Self {
+            reference,
+            rng,
+            mcts,
+        }
+    }
+
+    /// 次の一手を選ぶ
+    pub fn choose(&mut self, board: &Board) -> Choice {
+        match &mut self.mcts {
+            Some(mcts) => mcts.choose(board),
+            None => self.reference.choose(board, &mut self.rng),
+        }
+    }
+
+    /// 再利用する探索木を捨てる
+    pub fn reset(&mut self) {
+        if let Some(mcts) = &mut self.mcts {
+            mcts.reset();
+        }
+    }
+}
+
+/// 対戦相手ごとのベンチマークの結果
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BenchmarkResult {
+    pub name: String,
+    pub result: MatchResult,
+}
+
+impl BenchmarkResult {
+    /// 引き分けを0.5勝とした勝率
+    pub fn win_rate(&self) -> f64 {
+        (self.result.win as f64 + self.result.draw as f64 / 2.0) / self.result.games() as f64
+    }
+}
+
+/// CPUを各対戦相手と先後を入れ替えて対局させ、その結果を返す
+pub fn benchmark(
+    cpu: &CPU,
+    depth: usize,
+    references: &[Reference],
+    openings: &[Board],
+    rng: &mut impl Rng,
+) -> Vec<BenchmarkResult> {
+    references
+        .iter()
+        .map(|reference| {
+            let mut player = ReferencePlayer::new(*reference, rng.gen());
+            BenchmarkResult {
+                name: reference.name(),
+                result: eval_match_by(
+                    |board| cpu.choose_best(board, depth),
+                    |board| player.choose(board),
+                    openings,
+                ),
+            }
+        })
+        .collect()
+}
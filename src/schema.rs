@@ -0,0 +1,36 @@
+use crate::error::{Error, Result};
+use serde_json::Value;
+
+/// 1つ前のバージョンのJSONを次のバージョンに書き換える関数
+pub type Migration = fn(&mut Value);
+
+/// JSONの`version`(無ければ0)を読み、`migrations[i]`でバージョンiからi+1へ順に移行する
+/// 移行後は`version`を`migrations.len()`にする
+pub fn migrate(mut value: Value, name: &str, migrations: &[Migration]) -> Result<Value> {
+    let current = migrations.len() as u64;
+
+    let object = value
+        .as_object_mut()
+        .ok_or_else(|| Error::Parse(format!("{} must be a JSON object", name)))?;
+
+    let version = match object.get("version") {
+        None => 0,
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| Error::Parse(format!("{} has an invalid version: {}", name, version)))?,
+    };
+
+    if version > current {
+        return Err(Error::Parse(format!(
+            "{} version {} is newer than the supported version {}",
+            name, version, current
+        )));
+    }
+
+    for migration in &migrations[version as usize..] {
+        migration(&mut value);
+    }
+
+    value["version"] = Value::from(current);
+    Ok(value)
+}
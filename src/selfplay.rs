@@ -0,0 +1,105 @@
+use crate::board::{coordinate_to_notation, Board, Choice, JudgeResult, Player};
+use crate::config::Config;
+use crate::cpu::CPU;
+use crate::opening::make_openings;
+use crate::tournament::split_ranges;
+use crate::tuner::{set_results, winner, LabeledPosition};
+use rand::prelude::{SliceRandom, StdRng};
+use rand::{Rng, SeedableRng};
+
+/// 盤面から終局まで自己対局し、手を選んだ全ての局面を探索の評価値、打った手、最終結果付きで返す
+/// `random_prob`の確率で探索結果の代わりにランダムな手を打ち、その局面には評価値を付けない
+pub fn selfplay_game(
+    black: &CPU,
+    white: &CPU,
+    depth: usize,
+    random_prob: f64,
+    board: &Board,
+    rng: &mut impl Rng,
+) -> Vec<LabeledPosition> {
+    let mut board = board.clone();
+    let mut positions = Vec::new();
+
+    let judge = loop {
+        if board.is_skip() {
+            match board.update(Choice::Skip).unwrap() {
+                JudgeResult::Continue => continue,
+                result => break result,
+            }
+        }
+
+        let cpu = if board.player == Player::Black {
+            black
+        } else {
+            white
+        };
+        let (mut choice, score) = cpu.choose_best_with_score(&board, depth);
+        let mut score = Some(score);
+        if rng.gen::<f64>() < random_prob {
+            choice = *board.legal_choices().choose(rng).unwrap();
+            score = None;
+        }
+
+        positions.push(LabeledPosition {
+            board: board.clone(),
+            result: 0.5,
+            score,
+            choice: match choice {
+                Choice::Coordinate(co) => Some(coordinate_to_notation(co)),
+                Choice::Skip => None,
+            },
+        });
+
+        match board.update(choice).unwrap() {
+            JudgeResult::Continue => continue,
+            result => break result,
+        }
+    };
+
+    set_results(&mut positions, winner(judge, &board));
+    positions
+}
+
+/// `cpus`からランダムに選んだ2体で`selfplay_game_num`局の自己対局を行う
+/// 開始局面は`make_openings`で作り、`thread_num`スレッドで並列に対局する
+pub fn selfplay(cpus: &[CPU], config: &Config, rng: &mut impl Rng) -> Vec<LabeledPosition> {
+    assert!(!cpus.is_empty(), "No CPU for selfplay!");
+
+    let openings = make_openings(
+        config.selfplay_game_num,
+        config.opening_random_plies,
+        config.use_opening_book,
+        rng,
+    );
+
+    let mut handles = Vec::new();
+    for range in split_ranges(openings.len(), config.thread_num) {
+        let openings = openings[range].to_vec();
+        let cpus = cpus.to_vec();
+        let (depth, random_prob) = (config.selfplay_depth, config.selfplay_random_prob);
+        let mut rng = StdRng::seed_from_u64(rng.gen());
+
+        let handle = std::thread::spawn(move || {
+            let mut positions = Vec::new();
+            for opening in &openings {
+                let black = cpus.choose(&mut rng).unwrap();
+                let white = cpus.choose(&mut rng).unwrap();
+                positions.append(&mut selfplay_game(
+                    black,
+                    white,
+                    depth,
+                    random_prob,
+                    opening,
+                    &mut rng,
+                ));
+            }
+            positions
+        });
+        handles.push(handle);
+    }
+
+    handles
+        .into_iter()
+        .flat_map(|handle| handle.join().unwrap())
+        .collect()
+}
@@ -0,0 +1,68 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn handle_sigint(_signal: libc::c_int) {
+    // 2回目のCtrl-Cでは待たずに終了する
+    if STOP_REQUESTED.swap(true, Ordering::SeqCst) {
+        unsafe { libc::_exit(130) };
+    }
+}
+
+/// Ctrl-C(SIGINT)で即座に終了せず、停止の要求として記録するようにする
+pub fn install_stop_handler() {
+    #[cfg(unix)]
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            handle_sigint as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+/// 停止が要求されたか
+pub fn stop_requested() -> bool {
+    STOP_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// 学習を止める条件
+#[derive(Copy, Clone, Debug)]
+pub struct StopCondition {
+    /// この世代まで更新したら止める
+    pub max_generation: Option<usize>,
+    pub time_limit: Option<Duration>,
+    pub start: Instant,
+}
+
+impl StopCondition {
+    pub fn new(max_generation: Option<usize>, time_limit: Option<Duration>) -> Self {
+        Self {
+            max_generation,
+            time_limit,
+            start: Instant::now(),
+        }
+    }
+
+    /// 次に`generation`世代目を更新する前に止めるべきなら、その理由を返す
+    pub fn check(&self, generation: usize) -> Option<&'static str> {
+        if stop_requested() {
+            return Some("Interrupted");
+        }
+
+        if let Some(max_generation) = self.max_generation {
+            if generation > max_generation {
+                return Some("Reached max generation");
+            }
+        }
+
+        if let Some(time_limit) = self.time_limit {
+            if self.start.elapsed() >= time_limit {
+                return Some("Reached time limit");
+            }
+        }
+
+        None
+    }
+}
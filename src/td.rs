@@ -0,0 +1,227 @@
+use crate::board::{Board, Player};
+use crate::checkpoint::{load_checkpoint, save_checkpoint};
+use crate::config::Config;
+use crate::cpu::{CPU, WEIGHT_LEN};
+use crate::error::Result;
+use crate::opening::make_openings;
+use crate::schema::{migrate, Migration};
+use crate::selfplay::selfplay_game;
+use crate::tournament::split_ranges;
+use crate::tuner::{from_f64, sigmoid, to_f64, LabeledPosition};
+use rand::prelude::StdRng;
+use rand::{Rng, SeedableRng};
+use std::path::Path;
+
+/// TD(λ)で自己対局から重みを学習する
+/// 価値関数は`eval_board`と同じ線形の評価値を`scale`で勝率に変換したもの
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TdLearner {
+    /// チェックポイントの形式のバージョン
+    #[serde(default)]
+    pub version: u32,
+    /// 実数のままの各段階の重み
+    pub weights: [[f64; WEIGHT_LEN]; 4],
+    /// 終えた反復の回数
+    pub iteration: usize,
+    /// 乱数のシード(各反復の乱数はこれと反復の回数から決まる)
+    pub seed: u64,
+}
+
+/// `TD_MIGRATIONS[i]`でバージョンiからi+1に移行する
+/// バージョン0のファイルは無いので何もしない
+const TD_MIGRATIONS: [Migration; TdLearner::VERSION as usize] = [|_| ()];
+
+/// 1反復分の学習の結果
+#[derive(Clone, Debug, Default)]
+pub struct TdSummary {
+    /// 対局数
+    pub games: usize,
+    /// 学習に使った局面数
+    pub positions: usize,
+    /// TD誤差の二乗の平均
+    pub mean_squared_error: f64,
+}
+
+impl TdLearner {
+    pub const VERSION: u32 = 1;
+
+    /// `initial`の重みから学習を始める
+    pub fn new(initial: &CPU, seed: u64) -> Self {
+        Self {
+            version: Self::VERSION,
+            weights: to_f64(initial),
+            iteration: 0,
+            seed,
+        }
+    }
+
+    /// 重みを丸めたCPU
+    pub fn cpu(&self) -> CPU {
+        from_f64(&self.weights)
+    }
+
+    /// 盤面の黒から見た勝率とその重みに対する勾配
+    fn value(&self, board: &Board, scale: f64) -> (f64, usize, [f64; WEIGHT_LEN]) {
+        let stage = CPU::stage(board);
+        let features = CPU::features(board);
+        let eval = features
+            .iter()
+            .zip(&self.weights[stage])
+            .map(|(&feature, weight)| feature as f64 * weight)
+            .sum::<f64>();
+        let value = sigmoid(eval / scale);
+
+        let sign = if board.player == Player::Black {
+            1.0
+        } else {
+            -1.0
+        };
+        let mut gradient = [0.0; WEIGHT_LEN];
+        for (gradient, &feature) in gradient.iter_mut().zip(&features) {
+            *gradient = sign * value * (1.0 - value) / scale * feature as f64;
+        }
+
+        let value = if sign > 0.0 { value } else { 1.0 - value };
+        (value, stage, gradient)
+    }
+
+    /// 1局分の局面列から重みの更新量を求める
+    /// 最後の局面の次は最終結果を価値とする
+    fn game_delta(
+        &self,
+        positions: &[LabeledPosition],
+        config: &Config,
+    ) -> ([[f64; WEIGHT_LEN]; 4], f64) {
+        let mut delta = [[0.0; WEIGHT_LEN]; 4];
+        let mut traces = [[0.0; WEIGHT_LEN]; 4];
+        let mut squared_error = 0.0;
+
+        let values = positions
+            .iter()
+            .map(|position| self.value(&position.board, config.td_scale))
+            .collect::<Vec<_>>();
+        let black_result = positions.first().map_or(0.5, |position| {
+            if position.board.player == Player::Black {
+                position.result
+            } else {
+                1.0 - position.result
+            }
+        });
+
+        for (t, (value, stage, gradient)) in values.iter().enumerate() {
+            let next = values.get(t + 1).map_or(black_result, |next| next.0);
+            let error = next - value;
+            squared_error += error * error;
+
+            for traces in traces.iter_mut() {
+                for trace in traces.iter_mut() {
+                    *trace *= config.td_lambda;
+                }
+            }
+            for (trace, gradient) in traces[*stage].iter_mut().zip(gradient) {
+                *trace += gradient;
+            }
+
+            for (delta, traces) in delta.iter_mut().zip(&traces) {
+                for (delta, trace) in delta.iter_mut().zip(traces) {
+                    *delta += config.td_learning_rate * error * trace;
+                }
+            }
+        }
+
+        (delta, squared_error)
+    }
+
+    /// 反復の回数から、その反復に使う乱数生成器を作る
+    fn iteration_rng(&self) -> StdRng {
+        let iteration = (self.iteration as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        StdRng::seed_from_u64(self.seed ^ iteration)
+    }
+
+    /// 今の重みのCPU同士で`td_batch_games`局を並列に自己対局し、全局の更新量をまとめて反映する
+    pub fn learn_iteration(&mut self, config: &Config) -> TdSummary {
+        let mut rng = self.iteration_rng();
+        let openings = make_openings(
+            config.td_batch_games,
+            config.opening_random_plies,
+            config.use_opening_book,
+            &mut rng,
+        );
+        let cpu = self.cpu();
+
+        let mut handles = Vec::new();
+        for range in split_ranges(openings.len(), config.thread_num) {
+            let openings = openings[range].to_vec();
+            let (learner, cpu, config) = (self.clone(), cpu.clone(), config.clone());
+            let mut rng = StdRng::seed_from_u64(rng.gen());
+
+            let handle = std::thread::spawn(move || {
+                let mut delta = [[0.0; WEIGHT_LEN]; 4];
+                let mut summary = TdSummary::default();
+
+                for opening in &openings {
+                    let positions = selfplay_game(
+                        &cpu,
+                        &cpu,
+                        config.td_depth,
+                        config.td_random_prob,
+                        opening,
+                        &mut rng,
+                    );
+                    let (game_delta, squared_error) = learner.game_delta(&positions, &config);
+
+                    for (delta, game_delta) in delta.iter_mut().zip(&game_delta) {
+                        for (delta, game_delta) in delta.iter_mut().zip(game_delta) {
+                            *delta += game_delta;
+                        }
+                    }
+                    summary.games += 1;
+                    summary.positions += positions.len();
+                    summary.mean_squared_error += squared_error;
+                }
+
+                (delta, summary)
+            });
+            handles.push(handle);
+        }
+
+        let mut summary = TdSummary::default();
+        for handle in handles {
+            let (delta, thread_summary) = handle.join().unwrap();
+            for (weights, delta) in self.weights.iter_mut().zip(&delta) {
+                for (weight, delta) in weights.iter_mut().zip(delta) {
+                    *weight += delta / openings.len() as f64;
+                }
+            }
+            summary.games += thread_summary.games;
+            summary.positions += thread_summary.positions;
+            summary.mean_squared_error += thread_summary.mean_squared_error;
+        }
+
+        for weights in self.weights.iter_mut() {
+            for weight in weights.iter_mut() {
+                *weight = weight.clamp(-128.0, 127.0);
+            }
+        }
+        summary.mean_squared_error /= summary.positions.max(1) as f64;
+        self.iteration += 1;
+
+        summary
+    }
+
+    /// `Tournament::log`と同じく反復の回数付きのチェックポイントも残す
+    /// 常にJSON形式で保存する
+    pub fn log(&self, path: &Path, keep_num: usize) -> Result<()> {
+        let bytes = serde_json::to_vec(self)?;
+        Ok(save_checkpoint(path, self.iteration, &bytes, keep_num)?)
+    }
+
+    /// 最新のファイルから読み込み、壊れていれば番号付きのファイルを新しい順に試す
+    pub fn from_log_file(path: &Path) -> Result<Self> {
+        load_checkpoint(path, |bytes| {
+            let value = serde_json::from_slice(bytes)?;
+            let value = migrate(value, "TdLearner", &TD_MIGRATIONS)?;
+            Ok(serde_json::from_value(value)?)
+        })
+    }
+}
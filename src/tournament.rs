@@ -1,133 +1,504 @@
-use crate::cpu::{eval_cpu, random_cross, two_point_cross, CPU};
+use crate::binary::{decode_tournament, encode_tournament, is_binary};
+use crate::board::Board;
+use crate::checkpoint::{load_checkpoint, save_checkpoint};
+use crate::cma::{from_cpu, to_cpu, CmaState, DIM};
+use crate::config::{CheckpointFormat, Config, IslandConfig};
+use crate::cpu::{eval_match, eval_match_noisy, random_cross, two_point_cross, MatchResult, CPU};
+use crate::error::Result;
+use crate::opening::make_openings;
+use crate::schema::{migrate, Migration};
 use rand::prelude::{SliceRandom, StdRng};
 use rand::{Rng, SeedableRng};
-use std::fs::File;
-use std::io::{Read, Write};
+use std::ops::Range;
+use std::path::Path;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Tournament {
+    /// チェックポイントの形式のバージョン
+    #[serde(default)]
+    pub version: u32,
     pub cpus: Vec<CPU>,
     pub generation: usize,
+    /// 乱数のシード(各世代の乱数はこれと世代数から決まる)
+    #[serde(default)]
+    pub seed: u64,
+    /// 島の数(`cpus`を順に均等に分けたものをそれぞれ島とする)
+    #[serde(default = "default_island_num")]
+    pub island_num: usize,
+    /// 過去の世代の優勝CPU(古い順)
+    #[serde(default)]
+    pub hall_of_fame: Vec<CPU>,
+    /// CMA-ESで学習している場合の分布
+    #[serde(default)]
+    pub cma: Option<CmaState>,
+}
+
+/// `TOURNAMENT_MIGRATIONS[i]`でバージョンiからi+1に移行する
+/// バージョン0から1、1から2では追加された項目を既定値にするだけなので何もしない
+const TOURNAMENT_MIGRATIONS: [Migration; Tournament::VERSION as usize] = [|_| (), |_| ()];
+
+fn default_island_num() -> usize {
+    1
+}
+
+/// 1世代分の更新の結果
+#[derive(Clone, Debug)]
+pub struct GenerationSummary {
+    /// 勝敗がついた対局の数
+    pub decided: usize,
+    /// 引き分けの対局の数
+    pub draw: usize,
+    /// その世代で最も成績の良かったCPU
+    pub champion: CPU,
+    /// その世代で対局したCPU
+    pub population: Vec<CPU>,
+}
+
+/// 1グループ分の世代更新の結果
+struct GroupResult {
+    cpus: Vec<CPU>,
+    /// `cpus`の先頭から何体が突然変異していない上位CPUか
+    elite_num: usize,
+    /// グループ内で最も強かったCPUとその成績
+    champion: CPU,
+    champion_result: MatchResult,
+    /// グループ内の対局全体の集計
+    total: MatchResult,
+}
+
+/// 0..lenをできるだけ均等なn個の区間に分ける
+pub fn split_ranges(len: usize, n: usize) -> Vec<Range<usize>> {
+    let n = n.max(1).min(len);
+    let mut ranges = Vec::with_capacity(n);
+    let mut start = 0;
+
+    for i in 0..n {
+        let size = len / n + if i < len % n { 1 } else { 0 };
+        ranges.push(start..start + size);
+        start += size;
+    }
+
+    ranges
+}
+
+/// `learning_depth`で対局する
+/// `learning_noise`が有る場合のみ乱雑さを加え、`rng`を使う
+fn learning_match(
+    left: &CPU,
+    right: &CPU,
+    openings: &[Board],
+    config: &Config,
+    rng: &mut impl Rng,
+) -> MatchResult {
+    if config.learning_noise.is_none() {
+        eval_match(left, right, config.learning_depth, openings)
+    } else {
+        eval_match_noisy(
+            left,
+            right,
+            config.learning_depth,
+            &config.learning_noise,
+            openings,
+            rng,
+        )
+    }
+}
+
+/// グループ内での総当たり戦と殿堂入りCPUとの対局を行い、その結果から同じ数の次世代のCPUを作る
+fn next_group(
+    group: &[CPU],
+    hall_of_fame: &[CPU],
+    openings: &[Board],
+    island: &IslandConfig,
+    config: &Config,
+    rng: &mut impl Rng,
+) -> GroupResult {
+    let size = group.len();
+    let mut results = vec![MatchResult::default(); size];
+    let mut total = MatchResult::default();
+
+    for i in 0..size {
+        for j in 0..i {
+            let result = learning_match(&group[i], &group[j], openings, config, rng);
+            results[i].merge(&result);
+            results[j].merge(&result.reversed());
+            total.merge(&result);
+        }
+
+        for famer in hall_of_fame {
+            let result = learning_match(&group[i], famer, openings, config, rng);
+            results[i].merge(&result);
+            total.merge(&result);
+        }
+    }
+
+    // 勝ち点、石数差の順に強い順に並べる
+    let mut sort_by_strong = results.iter().enumerate().collect::<Vec<_>>();
+    sort_by_strong
+        .sort_by_key(|(_cpu_index, result)| (-(result.points() as isize), -result.disc_diff));
+    let champion_result = *sort_by_strong[0].1;
+    let sort_by_strong = sort_by_strong
+        .into_iter()
+        .map(|(cpu_index, _result)| &group[cpu_index])
+        .collect::<Vec<_>>();
+
+    let parent_num = config.parent_num.max(1).min(size);
+    let elite_num = config.elite_num.min(size);
+    let random_num = config.random_num.min(size - elite_num);
+    let cross_num = size - elite_num - random_num;
+
+    let mut pairs = Vec::new();
+    for i in 0..parent_num {
+        for j in i + 1..parent_num {
+            pairs.push((sort_by_strong[i], sort_by_strong[j]));
+        }
+    }
+
+    let mut cpus = Vec::with_capacity(size);
+
+    // 上位をそのまま残す
+    for cpu in sort_by_strong.iter().take(elite_num) {
+        cpus.push((*cpu).clone());
+    }
+
+    // 親の組を順に使い、2点交叉とランダム交叉を交互に一巡ずつ行う
+    // `cross_prob`が1未満の島では、交叉しない場合は親をそのまま使う
+    for k in 0..cross_num {
+        let skip_cross = island.cross_prob < 1.0 && island.cross_prob <= rng.gen::<f64>();
+        if pairs.is_empty() || skip_cross {
+            cpus.push(sort_by_strong[k % parent_num].clone());
+            continue;
+        }
+
+        let (left, right) = pairs[k % pairs.len()];
+        if (k / pairs.len()) % 2 == 0 {
+            cpus.push(two_point_cross(left, right, rng));
+        } else {
+            cpus.push(random_cross(left, right, rng));
+        }
+    }
+
+    // ランダム
+    for _ in 0..random_num {
+        cpus.push(CPU::new_random(rng));
+    }
+
+    // 上位以外を突然変異させる
+    for cpu in cpus.iter_mut().skip(elite_num) {
+        cpu.mutate_cpu(island.mutate_prob, rng);
+    }
+
+    GroupResult {
+        cpus,
+        elite_num,
+        champion: sort_by_strong[0].clone(),
+        champion_result,
+        total,
+    }
 }
 
 impl Tournament {
-    pub fn new_random(tournament_size: usize, rng: &mut impl Rng) -> Self {
+    /// 現在のチェックポイントの形式のバージョン
+    pub const VERSION: u32 = 2;
+
+    /// シードから最初の世代を作る
+    pub fn new_random(tournament_size: usize, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
         let mut cpus = Vec::with_capacity(tournament_size);
         for _ in 0..tournament_size {
-            cpus.push(CPU::new_random(rng));
+            cpus.push(CPU::new_random(&mut rng));
         }
 
         Self {
+            version: Self::VERSION,
             cpus,
             generation: 1,
+            seed,
+            island_num: 1,
+            hall_of_fame: Vec::new(),
+            cma: None,
         }
     }
 
-    pub fn log(&self, log_file: &mut File) -> std::io::Result<()> {
-        let str = serde_json::to_string(self)?;
-        log_file.set_len(0)?;
-        log_file.write_all(str.as_bytes())?;
-        log_file.flush()
+    /// 世代番号付きのファイルと最新のファイルに書き込み、古いものは`keep_num`個まで残す
+    pub fn log(&self, path: &Path, keep_num: usize, format: CheckpointFormat) -> Result<()> {
+        let bytes = match format {
+            CheckpointFormat::Json => serde_json::to_vec(self)?,
+            CheckpointFormat::Binary => encode_tournament(self),
+        };
+        Ok(save_checkpoint(path, self.generation, &bytes, keep_num)?)
+    }
+
+    /// 最新のファイルから読み込み、壊れていれば世代番号付きのファイルを新しい順に試す
+    /// JSON形式とバイナリ形式のどちらでも読める
+    pub fn from_log_file(path: &Path) -> Result<Self> {
+        load_checkpoint(path, |bytes| {
+            if is_binary(bytes) {
+                decode_tournament(bytes)
+            } else {
+                Self::from_json(bytes)
+            }
+        })
+    }
+
+    /// 古いバージョンのJSONは移行してから読み込む
+    pub fn from_json(bytes: &[u8]) -> Result<Self> {
+        let value = serde_json::from_slice(bytes)?;
+        let value = migrate(value, "Tournament", &TOURNAMENT_MIGRATIONS)?;
+        Ok(serde_json::from_value(value)?)
     }
 
-    pub fn from_log_file(log_file: &mut File) -> std::io::Result<Self> {
-        let mut buf = String::new();
-        log_file.read_to_string(&mut buf)?;
-        let tournament = serde_json::from_str(&buf)?;
-        Ok(tournament)
+    /// シードと世代数から、その世代の更新に使う乱数生成器を作る
+    /// 同じチェックポイントから再開すれば同じ乱数列になる
+    fn generation_rng(&self) -> StdRng {
+        let generation = (self.generation as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        StdRng::seed_from_u64(self.seed ^ generation)
     }
 
-    pub fn upgrade_generation(&mut self, mutate_prob: f64, depth: usize, rng: &mut impl Rng) {
-        assert_eq!(self.cpus.len(), 4096);
-        self.cpus.shuffle(rng);
-    
-        let mut handles = Vec::with_capacity(4096);
-        let mut cpus = Vec::with_capacity(4096);
+    /// 各島の`cpus`での範囲
+    pub fn islands(&self) -> Vec<Range<usize>> {
+        split_ranges(self.cpus.len(), self.island_num)
+    }
+
+    /// 島ごとにCPUを`select_tournament_size`程度のグループに分けて総当たり戦を行い、次世代を作る
+    /// 各グループの優勝CPUのうち最も成績の良いものを殿堂入りさせ、
+    /// `migration_interval`世代ごとに各島の上位を隣の島へ移住させる
+    pub fn upgrade_generation(&mut self, config: &Config) -> GenerationSummary {
+        assert!(!self.cpus.is_empty(), "Tournament has no CPU!");
+        let mut rng = self.generation_rng();
+        let rng = &mut rng;
+
+        let island_configs = config.island_configs();
+        self.island_num = island_configs.len();
+        let islands = self.islands();
+
+        // 島の中でだけ混ぜてグループに分ける
+        let mut groups = Vec::new();
+        for (island, range) in islands.iter().enumerate() {
+            self.cpus[range.clone()].shuffle(rng);
+
+            let group_num = range.len() / config.select_tournament_size.max(1);
+            for group in split_ranges(range.len(), group_num) {
+                groups.push((island, range.start + group.start..range.start + group.end));
+            }
+        }
 
-        for i in 0..64 {
-            let thread_cpu = self.cpus.clone();
+        // 全グループで同じ開始局面を使う
+        let openings = make_openings(
+            config.opening_num,
+            config.opening_random_plies,
+            config.use_opening_book,
+            rng,
+        );
+
+        // 全CPUが同じ殿堂入りCPUと対局する
+        let hall_of_fame = self
+            .hall_of_fame
+            .choose_multiple(rng, config.hall_of_fame_opponent_num)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let thread_groups = split_ranges(groups.len(), config.thread_num);
+
+        let mut handles = Vec::with_capacity(thread_groups.len());
+        let mut cpus = Vec::with_capacity(self.cpus.len());
+        // (島, 優勝CPU, 成績)
+        let mut champions = Vec::with_capacity(groups.len());
+        // 島ごとの突然変異させたCPUの位置
+        let mut non_elites = vec![Vec::new(); islands.len()];
+        let mut total = MatchResult::default();
+
+        for thread_range in thread_groups {
+            let thread_cpu = groups[thread_range]
+                .iter()
+                .map(|(island, range)| (*island, self.cpus[range.clone()].to_vec()))
+                .collect::<Vec<_>>();
+            let island_configs = island_configs.clone();
+            let hall_of_fame = hall_of_fame.clone();
+            let openings = openings.clone();
+            let config = config.clone();
             let mut rng = StdRng::from_seed(rng.gen());
 
             let handle = std::thread::spawn(move || {
-                let thread_cpu = &thread_cpu[i * 64..(i + 1) * 64];
-                let mut cpus = Vec::with_capacity(64);
-
-                for i in 0..8 {
-                    let tournament_cpus = &thread_cpu[i * 8..(i + 1) * 8];
-                    let mut win_score = [0; 8];
-
-                    for i in 0..8 {
-                        for j in 0..8 {
-                            if i <= j {
-                                continue;
-                            }
-
-                            let left = &tournament_cpus[i];
-                            let right = &tournament_cpus[j];
-
-                            let winner = eval_cpu(left, right, depth);
-                            if winner == left {
-                                win_score[i] += 1;
-                            } else {
-                                win_score[j] += 1;
-                            }
-                        }
-                    }
-
-                    let mut sort_by_strong = win_score.iter().enumerate().collect::<Vec<_>>();
-                    sort_by_strong.sort_by_key(|(_cpu_index, &win_num)| -win_num);
-                    let sort_by_strong = sort_by_strong
-                        .into_iter()
-                        .map(|(cpu_index, _win_num)| &tournament_cpus[cpu_index])
-                        .collect::<Vec<_>>();
-
-                    let mut cpu_vec = Vec::with_capacity(64);
-                    // 最優秀1体
-                    cpu_vec.push(sort_by_strong[0].clone());
-
-                    // 2点交叉3体
-                    cpu_vec.push(two_point_cross(
-                        sort_by_strong[0],
-                        sort_by_strong[1],
-                        &mut rng,
-                    ));
-                    cpu_vec.push(two_point_cross(
-                        sort_by_strong[0],
-                        sort_by_strong[2],
-                        &mut rng,
-                    ));
-                    cpu_vec.push(two_point_cross(
-                        sort_by_strong[1],
-                        sort_by_strong[2],
-                        &mut rng,
-                    ));
-
-                    // ランダム交叉1体
-                    cpu_vec.push(random_cross(sort_by_strong[0], sort_by_strong[1], &mut rng));
-                    cpu_vec.push(random_cross(sort_by_strong[0], sort_by_strong[2], &mut rng));
-                    cpu_vec.push(random_cross(sort_by_strong[1], sort_by_strong[2], &mut rng));
-
-                    // ランダム1体
-                    cpu_vec.push(CPU::new_random(&mut rng));
-
-                    cpus.append(&mut cpu_vec);
-                }
-
-                cpus
+                thread_cpu
+                    .iter()
+                    .map(|(island, group)| {
+                        let island_config = &island_configs[*island];
+                        let result = next_group(
+                            group,
+                            &hall_of_fame,
+                            &openings,
+                            island_config,
+                            &config,
+                            &mut rng,
+                        );
+                        (*island, result)
+                    })
+                    .collect::<Vec<_>>()
             });
 
             handles.push(handle);
         }
 
         for handle in handles {
-            cpus.append(&mut handle.join().unwrap());
+            for (island, mut group_result) in handle.join().unwrap() {
+                let start = cpus.len() + group_result.elite_num;
+                cpus.append(&mut group_result.cpus);
+                non_elites[island].extend(start..cpus.len());
+                total.merge(&group_result.total);
+                champions.push((island, group_result.champion, group_result.champion_result));
+            }
+        }
+
+        // 島の数が1より多い場合のみ移住させる
+        if islands.len() > 1 && self.generation % config.migration_interval.max(1) == 0 {
+            for island in 0..islands.len() {
+                let mut migrants = champions
+                    .iter()
+                    .filter(|(from, _cpu, _result)| *from == island)
+                    .collect::<Vec<_>>();
+                migrants.sort_by_key(|(_from, _cpu, result)| {
+                    (-(result.points() as isize), -result.disc_diff)
+                });
+
+                let target = (island + 1) % islands.len();
+                let positions = non_elites[target]
+                    .choose_multiple(rng, config.migration_num.min(migrants.len()))
+                    .cloned()
+                    .collect::<Vec<_>>();
+
+                for (position, (_from, migrant, _result)) in positions.into_iter().zip(migrants) {
+                    cpus[position] = migrant.clone();
+                }
+            }
+        }
+
+        let (_island, champion, _result) = champions
+            .into_iter()
+            .rev()
+            .max_by_key(|(_island, _cpu, result)| (result.points(), result.disc_diff))
+            .unwrap();
+        self.push_hall_of_fame(&champion, config.hall_of_fame_size);
+
+        let population = std::mem::replace(&mut self.cpus, cpus);
+        self.generation += 1;
+
+        GenerationSummary {
+            decided: total.win + total.lose,
+            draw: total.draw,
+            champion,
+            population,
+        }
+    }
+
+    /// 殿堂入りさせ、古いものから`size`体を超えた分を除く
+    fn push_hall_of_fame(&mut self, champion: &CPU, size: usize) {
+        self.hall_of_fame.push(champion.clone());
+        if self.hall_of_fame.len() > size {
+            let over = self.hall_of_fame.len() - size;
+            self.hall_of_fame.drain(..over);
         }
+    }
+
+    /// `upgrade_generation`の代わりにCMA-ESで1世代分更新する
+    /// 分布から`cma_population`体を作って総当たり戦と殿堂入りCPUとの対局を行い、その順位で分布を更新する
+    /// 対局したCPUは強い順に`cpus`に残す
+    pub fn upgrade_generation_cma(&mut self, config: &Config) -> GenerationSummary {
+        let mut rng = self.generation_rng();
+        let rng = &mut rng;
+
+        // 分布が無ければ今のCPUの重みの平均から始める
+        let mut state = self.cma.take().unwrap_or_else(|| {
+            let mut mean = vec![0.0; DIM];
+            for cpu in &self.cpus {
+                for (mean, x) in mean.iter_mut().zip(from_cpu(cpu)) {
+                    *mean += x / self.cpus.len().max(1) as f64;
+                }
+            }
+            CmaState::new(mean, config.cma_sigma)
+        });
 
-        for cpu in &mut cpus {
-            cpu.mutate_cpu(mutate_prob, rng);
+        let samples = state.sample(config.cma_population.max(2), rng);
+        let cpus = samples.iter().map(|x| to_cpu(x)).collect::<Vec<_>>();
+        let size = cpus.len();
+
+        let openings = make_openings(
+            config.opening_num,
+            config.opening_random_plies,
+            config.use_opening_book,
+            rng,
+        );
+        let hall_of_fame = self
+            .hall_of_fame
+            .choose_multiple(rng, config.hall_of_fame_opponent_num)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        // (i, j)はjが`size`未満ならCPU同士、それ以上なら殿堂入りCPUとの対局
+        let mut pairs = Vec::new();
+        for i in 0..size {
+            for j in (0..i).chain(size..size + hall_of_fame.len()) {
+                pairs.push((i, j));
+            }
         }
 
-        self.cpus = cpus;
+        let mut handles = Vec::new();
+        for range in split_ranges(pairs.len(), config.thread_num) {
+            let pairs = pairs[range].to_vec();
+            let cpus = cpus.clone();
+            let hall_of_fame = hall_of_fame.clone();
+            let openings = openings.clone();
+            let config = config.clone();
+            let mut rng = StdRng::seed_from_u64(rng.gen());
+
+            let handle = std::thread::spawn(move || {
+                pairs
+                    .into_iter()
+                    .map(|(i, j)| {
+                        let right = cpus.get(j).unwrap_or_else(|| &hall_of_fame[j - size]);
+                        let result = learning_match(&cpus[i], right, &openings, &config, &mut rng);
+                        (i, j, result)
+                    })
+                    .collect::<Vec<_>>()
+            });
+            handles.push(handle);
+        }
+
+        let mut results = vec![MatchResult::default(); size];
+        let mut total = MatchResult::default();
+        for handle in handles {
+            for (i, j, result) in handle.join().unwrap() {
+                results[i].merge(&result);
+                if j < size {
+                    results[j].merge(&result.reversed());
+                }
+                total.merge(&result);
+            }
+        }
+
+        // 勝ち点、石数差の順に強い順に並べる
+        let mut order = (0..size).collect::<Vec<_>>();
+        order.sort_by_key(|&i| (-(results[i].points() as isize), -results[i].disc_diff));
+
+        let sorted_samples = order
+            .iter()
+            .map(|&i| samples[i].clone())
+            .collect::<Vec<_>>();
+        state.update(&sorted_samples);
+
+        let champion = cpus[order[0]].clone();
+        self.push_hall_of_fame(&champion, config.hall_of_fame_size);
+
+        self.cpus = order.iter().map(|&i| cpus[i].clone()).collect();
+        self.island_num = 1;
+        self.cma = Some(state);
         self.generation += 1;
+
+        GenerationSummary {
+            decided: total.win + total.lose,
+            draw: total.draw,
+            champion,
+            population: self.cpus.clone(),
+        }
     }
 }
@@ -0,0 +1,241 @@
+use crate::board::{coordinate_from_notation, Board, Choice, JudgeResult, Player};
+use crate::cpu::{CPU, WEIGHT_LEN};
+use crate::error::{Error, Result};
+use std::fs::File;
+use std::io::{Read, Write};
+
+/// 学習に使う、結果の分かっている局面
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LabeledPosition {
+    pub board: Board,
+    /// 次の手の人から見た最終結果(勝ち1.0、引き分け0.5、負け0.0)
+    pub result: f64,
+    /// 次の手の人から見た探索による評価値
+    #[serde(default)]
+    pub score: Option<isize>,
+    /// この局面で打った手("f5"など)
+    #[serde(default)]
+    pub choice: Option<String>,
+}
+
+impl LabeledPosition {
+    /// JSON Lines形式で1行追記する
+    pub fn log(&self, log_file: &mut File) -> Result<()> {
+        let str = serde_json::to_string(self)?;
+        log_file.write_all(format!("{}\n", str).as_bytes())?;
+        Ok(log_file.flush()?)
+    }
+}
+
+/// 何に合わせて重みを調整するか
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub enum TuneTarget {
+    /// 最終結果
+    Result,
+    /// 探索による評価値(無い局面は最終結果を使う)
+    Score,
+}
+
+/// 終局していればその勝者、途中なら石数の多い方を勝者とする(引き分けは`None`)
+pub fn winner(judge: JudgeResult, board: &Board) -> Option<Player> {
+    let (player, opponent) = board.calc_now_score();
+    match judge {
+        JudgeResult::Win(winner) => Some(winner),
+        JudgeResult::Draw => None,
+        JudgeResult::Continue if player > opponent => Some(board.player),
+        JudgeResult::Continue if player < opponent => Some(board.player.next()),
+        JudgeResult::Continue => None,
+    }
+}
+
+/// 各局面の`result`を、その局面の次の手の人から見た勝敗にする
+pub fn set_results(positions: &mut [LabeledPosition], winner: Option<Player>) {
+    for position in positions {
+        position.result = match winner {
+            Some(winner) if winner == position.board.player => 1.0,
+            Some(_) => 0.0,
+            None => 0.5,
+        };
+    }
+}
+
+/// "f5d6c3..."のような1局分の棋譜を打ち、全ての局面に最終結果を付ける
+/// 打てる手が無い場合は自動でパスする
+pub fn positions_from_moves(moves: &str) -> Result<Vec<LabeledPosition>> {
+    let mut board = Board::new();
+    let mut positions = Vec::new();
+    let chars = moves.trim().chars().collect::<Vec<_>>();
+    let mut judge = JudgeResult::Continue;
+
+    for notation in chars.chunks(2) {
+        if judge != JudgeResult::Continue {
+            return Err(Error::GameOver);
+        }
+
+        if board.is_skip() {
+            board.update(Choice::Skip)?;
+        }
+
+        let notation = notation.iter().collect::<String>();
+        let co = coordinate_from_notation(&notation)
+            .ok_or_else(|| Error::Parse(format!("Invalid notation: {}", notation)))?;
+
+        positions.push(LabeledPosition {
+            board: board.clone(),
+            result: 0.5,
+            score: None,
+            choice: Some(notation),
+        });
+        judge = board.update(Choice::Coordinate(co))?;
+    }
+
+    // 途中までの棋譜は石数の多い方を勝ちとする
+    set_results(&mut positions, winner(judge, &board));
+    Ok(positions)
+}
+
+/// 1行に1局面のJSON、または1行に1局の棋譜のファイルを読み込む
+pub fn load_dataset(file: &mut File) -> Result<Vec<LabeledPosition>> {
+    let mut buf = String::new();
+    file.read_to_string(&mut buf)?;
+
+    let mut positions = Vec::new();
+    for line in buf.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if line.starts_with('{') {
+            positions.push(serde_json::from_str(line)?);
+        } else {
+            let mut game = positions_from_moves(line)?;
+            positions.append(&mut game);
+        }
+    }
+
+    Ok(positions)
+}
+
+/// 評価値を勝率に変換する
+pub fn sigmoid(x: f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+/// 評価値を勝率に変換したときの、局面のラベルとの平均二乗誤差を最小化するように重みを調整する
+pub struct Tuner {
+    /// 評価値を勝率に変換するときの尺度
+    pub scale: f64,
+    pub learning_rate: f64,
+    pub target: TuneTarget,
+    /// (段階, 特徴量, 目標の勝率)
+    samples: Vec<(usize, [f64; WEIGHT_LEN], f64)>,
+}
+
+impl Tuner {
+    pub fn new(
+        positions: &[LabeledPosition],
+        target: TuneTarget,
+        scale: f64,
+        learning_rate: f64,
+    ) -> Self {
+        let samples = positions
+            .iter()
+            .map(|position| {
+                let mut features = [0.0; WEIGHT_LEN];
+                for (feature, &value) in features.iter_mut().zip(&CPU::features(&position.board)) {
+                    *feature = value as f64;
+                }
+
+                let label = match (target, position.score) {
+                    (TuneTarget::Score, Some(score)) => sigmoid(score as f64 / scale),
+                    _ => position.result,
+                };
+
+                (CPU::stage(&position.board), features, label)
+            })
+            .collect();
+
+        Self {
+            scale,
+            learning_rate,
+            target,
+            samples,
+        }
+    }
+
+    /// 重みに対する平均二乗誤差とその勾配
+    fn loss_and_gradient(&self, weights: &[[f64; WEIGHT_LEN]; 4]) -> (f64, [[f64; WEIGHT_LEN]; 4]) {
+        let mut loss = 0.0;
+        let mut gradient = [[0.0; WEIGHT_LEN]; 4];
+        let len = self.samples.len().max(1) as f64;
+
+        for (stage, features, label) in &self.samples {
+            let eval = features
+                .iter()
+                .zip(&weights[*stage])
+                .map(|(feature, weight)| feature * weight)
+                .sum::<f64>();
+            let predicted = sigmoid(eval / self.scale);
+            let error = predicted - label;
+            loss += error * error / len;
+
+            let coefficient = 2.0 * error * predicted * (1.0 - predicted) / self.scale / len;
+            for (gradient, feature) in gradient[*stage].iter_mut().zip(features) {
+                *gradient += coefficient * feature;
+            }
+        }
+
+        (loss, gradient)
+    }
+
+    pub fn loss(&self, cpu: &CPU) -> f64 {
+        self.loss_and_gradient(&to_f64(cpu)).0
+    }
+
+    /// `initial`から始めてAdamで`epochs`回更新し、`i8`に丸めたCPUを返す
+    pub fn tune(&self, initial: &CPU, epochs: usize, mut report: impl FnMut(usize, f64)) -> CPU {
+        let (beta1, beta2, epsilon) = (0.9, 0.999, 1e-8);
+        let mut weights = to_f64(initial);
+        let mut m = [[0.0; WEIGHT_LEN]; 4];
+        let mut v = [[0.0; WEIGHT_LEN]; 4];
+
+        for epoch in 1..=epochs {
+            let (loss, gradient) = self.loss_and_gradient(&weights);
+            report(epoch, loss);
+
+            for stage in 0..4 {
+                for k in 0..WEIGHT_LEN {
+                    let g = gradient[stage][k];
+                    m[stage][k] = beta1 * m[stage][k] + (1.0 - beta1) * g;
+                    v[stage][k] = beta2 * v[stage][k] + (1.0 - beta2) * g * g;
+
+                    let m_hat = m[stage][k] / (1.0 - beta1.powi(epoch as i32));
+                    let v_hat = v[stage][k] / (1.0 - beta2.powi(epoch as i32));
+                    weights[stage][k] -= self.learning_rate * m_hat / (v_hat.sqrt() + epsilon);
+                    weights[stage][k] = weights[stage][k].clamp(-128.0, 127.0);
+                }
+            }
+        }
+
+        from_f64(&weights)
+    }
+}
+
+/// CPUの重みを実数にする
+pub fn to_f64(cpu: &CPU) -> [[f64; WEIGHT_LEN]; 4] {
+    let mut weights = [[0.0; WEIGHT_LEN]; 4];
+    for (stage, stage_weights) in cpu.stages().iter().enumerate() {
+        for (k, &weight) in stage_weights.iter().enumerate() {
+            weights[stage][k] = weight as f64;
+        }
+    }
+    weights
+}
+
+/// 実数の重みを丸めてCPUにする
+pub fn from_f64(weights: &[[f64; WEIGHT_LEN]; 4]) -> CPU {
+    let mut stages = [[0; WEIGHT_LEN]; 4];
+    for (stage, stage_weights) in stages.iter_mut().enumerate() {
+        for (k, weight) in stage_weights.iter_mut().enumerate() {
+            *weight = weights[stage][k].round().clamp(-128.0, 127.0) as i8;
+        }
+    }
+
+    CPU::from_stages(stages)
+}